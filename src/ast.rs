@@ -1,77 +1,464 @@
-use token::Token;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use token::{Span, Token};
+
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Node<'a> {
     LetStatement {
         token: Token<'a>,
-        name: Box<Node<'a>>,
+        span: Span,
+        // Whether the bindings were declared with `let mut`
+        mutable: bool,
+        pattern: Pattern<'a>,
         value: Box<Node<'a>>,
     },
     ReturnStatement {
         token: Token<'a>,
+        span: Span,
         value: Option<Box<Node<'a>>>,
     },
     Identifier {
         token: Token<'a>,
+        span: Span,
         value: &'a str,
     },
     Expression {
         token: Token<'a>,
+        span: Span,
         value: Box<Node<'a>>,
     },
     IntegerLiteral {
         token: Token<'a>,
-        value: u64,
+        span: Span,
+        value: i64,
+    },
+    // An integer literal outside the i64 range, with the `bigint` feature
+    #[cfg(feature = "bigint")]
+    BigIntegerLiteral {
+        token: Token<'a>,
+        span: Span,
+        value: BigInt,
+    },
+    FloatLiteral {
+        token: Token<'a>,
+        span: Span,
+        value: f64,
     },
     Boolean {
         token: Token<'a>,
+        span: Span,
         value: bool,
     },
+    StringLiteral {
+        token: Token<'a>,
+        span: Span,
+        // The contents with escape sequences expanded
+        value: String,
+    },
+    ArrayLiteral {
+        token: Token<'a>,
+        span: Span,
+        elements: Vec<Node<'a>>,
+    },
+    // Key and value expressions, in source order
+    HashLiteral {
+        token: Token<'a>,
+        span: Span,
+        pairs: Vec<(Node<'a>, Node<'a>)>,
+    },
     PrefixExpression {
         token: Token<'a>,
+        span: Span,
         operator: &'a str,
         right: Option<Box<Node<'a>>>,
     },
     InfixExpression {
         token: Token<'a>,
+        span: Span,
         operator: &'a str,
         left: Box<Node<'a>>,
         right: Option<Box<Node<'a>>>,
     },
     BlockStatement {
         token: Token<'a>,
+        span: Span,
         statements: Vec<Box<Node<'a>>>,
     },
+    // `fn name(parameters) { body }` as a statement, which binds `name`
+    FunctionDeclaration {
+        token: Token<'a>,
+        span: Span,
+        name: Box<Node<'a>>,
+        // Shared with the function values the declaration evaluates to
+        parameters: Rc<Vec<Node<'a>>>,
+        body: Rc<Node<'a>>,
+    },
     FunctionLiteral {
         token: Token<'a>,
-        parameters: Vec<Node<'a>>,
-        body: Box<Node<'a>>,
+        span: Span,
+        parameters: Rc<Vec<Node<'a>>>,
+        body: Rc<Node<'a>>,
     },
     CallExpression {
         token: Token<'a>,
+        span: Span,
         fn_name: Box<Node<'a>>,
         parameters: Vec<Node<'a>>,
     },
+    IndexExpression {
+        token: Token<'a>,
+        span: Span,
+        left: Box<Node<'a>>,
+        index: Box<Node<'a>>,
+    },
+    // `target = value`, or a compound form such as `target += value`, where
+    // the token is the assignment operator. The target is an Identifier or
+    // an IndexExpression into one.
+    AssignExpression {
+        token: Token<'a>,
+        span: Span,
+        target: Box<Node<'a>>,
+        value: Box<Node<'a>>,
+    },
+    IfExpression {
+        token: Token<'a>,
+        span: Span,
+        condition: Box<Node<'a>>,
+        consequence: Box<Node<'a>>,
+        // Either a BlockStatement or, for `else if`, another IfExpression
+        alternative: Option<Box<Node<'a>>>,
+    },
+    WhileStatement {
+        token: Token<'a>,
+        span: Span,
+        condition: Box<Node<'a>>,
+        body: Box<Node<'a>>,
+    },
+    ForStatement {
+        token: Token<'a>,
+        span: Span,
+        init: Option<Box<Node<'a>>>,
+        // A missing condition loops until `break` or `return`
+        condition: Option<Box<Node<'a>>>,
+        update: Option<Box<Node<'a>>>,
+        body: Box<Node<'a>>,
+    },
+    LoopStatement {
+        token: Token<'a>,
+        span: Span,
+        body: Box<Node<'a>>,
+    },
+    BreakStatement {
+        token: Token<'a>,
+        span: Span,
+    },
+    ContinueStatement {
+        token: Token<'a>,
+        span: Span,
+    },
 }
 
 
 impl<'a> Node<'a> {
-    pub fn get_token_literal(&self) -> Token {
+    pub fn get_token_literal(&self) -> Token<'a> {
         match *self {
             Node::LetStatement { token: t, .. } => t,
             Node::ReturnStatement { token: t, .. } => t,
             Node::Identifier { token: t, .. } => t,
             Node::Expression { token: t, .. } => t,
             Node::IntegerLiteral { token: t, .. } => t,
+            #[cfg(feature = "bigint")]
+            Node::BigIntegerLiteral { token: t, .. } => t,
+            Node::FloatLiteral { token: t, .. } => t,
             Node::PrefixExpression { token: t, .. } => t,
             Node::InfixExpression { token: t, .. } => t,
             Node::BlockStatement { token: t, .. } => t,
+            Node::FunctionDeclaration { token: t, .. } => t,
             Node::FunctionLiteral { token: t, .. } => t,
             Node::CallExpression { token: t, .. } => t,
             Node::Boolean { token: t, .. } => t,
+            Node::StringLiteral { token: t, .. } => t,
+            Node::ArrayLiteral { token: t, .. } => t,
+            Node::HashLiteral { token: t, .. } => t,
+            Node::IndexExpression { token: t, .. } => t,
+            Node::AssignExpression { token: t, .. } => t,
+            Node::IfExpression { token: t, .. } => t,
+            Node::WhileStatement { token: t, .. } => t,
+            Node::ForStatement { token: t, .. } => t,
+            Node::LoopStatement { token: t, .. } => t,
+            Node::BreakStatement { token: t, .. } => t,
+            Node::ContinueStatement { token: t, .. } => t,
             // _ => panic!("Expected a valid token"),
         }
     }
+
+    pub fn span(&self) -> Span {
+        match *self {
+            #[cfg(feature = "bigint")]
+            Node::BigIntegerLiteral { span, .. } => span,
+            Node::LetStatement { span, .. } |
+            Node::ReturnStatement { span, .. } |
+            Node::Identifier { span, .. } |
+            Node::Expression { span, .. } |
+            Node::IntegerLiteral { span, .. } |
+            Node::FloatLiteral { span, .. } |
+            Node::PrefixExpression { span, .. } |
+            Node::InfixExpression { span, .. } |
+            Node::BlockStatement { span, .. } |
+            Node::FunctionDeclaration { span, .. } |
+            Node::FunctionLiteral { span, .. } |
+            Node::CallExpression { span, .. } |
+            Node::ArrayLiteral { span, .. } |
+            Node::HashLiteral { span, .. } |
+            Node::IndexExpression { span, .. } |
+            Node::AssignExpression { span, .. } |
+            Node::Boolean { span, .. } |
+            Node::StringLiteral { span, .. } |
+            Node::IfExpression { span, .. } |
+            Node::WhileStatement { span, .. } |
+            Node::ForStatement { span, .. } |
+            Node::LoopStatement { span, .. } |
+            Node::BreakStatement { span, .. } |
+            Node::ContinueStatement { span, .. } => span,
+        }
+    }
+
+    /// The left operand of an infix, call or index expression. A chain of
+    /// these operators nests to the left one level per operator, so code that
+    /// walks the tree follows this in a loop rather than by recursing.
+    pub fn chained_operand(&self) -> Option<&Node<'a>> {
+        match *self {
+            Node::InfixExpression { ref left, .. } |
+            Node::IndexExpression { ref left, .. } |
+            Node::CallExpression { fn_name: ref left, .. } => Some(left),
+            _ => None,
+        }
+    }
+
+    // Detaches the chained operand, if it is itself part of the chain
+    fn take_chained_operand(&mut self) -> Option<Box<Node<'a>>> {
+        let operand = match *self {
+            Node::InfixExpression { ref mut left, .. } |
+            Node::IndexExpression { ref mut left, .. } |
+            Node::CallExpression { fn_name: ref mut left, .. } => left,
+            _ => return None,
+        };
+        operand.chained_operand()?;
+
+        let placeholder = Node::Boolean {
+            token: Token::TRUE,
+            span: operand.span(),
+            value: true,
+        };
+        Some(mem::replace(operand, Box::new(placeholder)))
+    }
+}
+
+impl<'a> Drop for Node<'a> {
+    // Drops a chain of operators one link at a time, as dropping it
+    // recursively could overflow the stack
+    fn drop(&mut self) {
+        let mut next = self.take_chained_operand();
+        while let Some(mut node) = next {
+            next = node.take_chained_operand();
+        }
+    }
+}
+
+/// Writes `items` separated by `separator`.
+pub fn write_separated<T: fmt::Display>(f: &mut fmt::Formatter,
+                                        items: &[T],
+                                        separator: &str)
+                                        -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+// Prints the node back as source, with every prefix and infix expression
+// wrapped in parentheses so that precedence is explicit.
+impl<'a> fmt::Display for Node<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Node::LetStatement { mutable, ref pattern, ref value, .. } => {
+                let keyword = if mutable { "let mut" } else { "let" };
+                write!(f, "{} {} = {};", keyword, pattern, value)
+            }
+            Node::ReturnStatement { ref value, .. } => {
+                match *value {
+                    Some(ref value) => write!(f, "return {};", value),
+                    None => write!(f, "return;"),
+                }
+            }
+            Node::Identifier { value, .. } => write!(f, "{}", value),
+            Node::Expression { ref value, .. } => write!(f, "{}", value),
+            Node::IntegerLiteral { value, .. } => write!(f, "{}", value),
+            #[cfg(feature = "bigint")]
+            Node::BigIntegerLiteral { ref value, .. } => write!(f, "{}", value),
+            Node::FloatLiteral { value, .. } => write!(f, "{:?}", value),
+            Node::Boolean { value, .. } => write!(f, "{}", value),
+            Node::StringLiteral { token, .. } => write!(f, "{}", token),
+            Node::PrefixExpression { token, ref right, .. } => {
+                match *right {
+                    Some(ref right) => write!(f, "({}{})", token, right),
+                    None => write!(f, "({})", token),
+                }
+            }
+            Node::InfixExpression { token, ref left, ref right, .. } => {
+                match *right {
+                    Some(ref right) => write!(f, "({} {} {})", left, token, right),
+                    None => write!(f, "({} {})", left, token),
+                }
+            }
+            Node::BlockStatement { ref statements, .. } => {
+                if statements.is_empty() {
+                    return write!(f, "{{}}");
+                }
+                write!(f, "{{ ")?;
+                write_separated(f, statements, " ")?;
+                write!(f, " }}")
+            }
+            Node::FunctionDeclaration { ref name, ref parameters, ref body, .. } => {
+                write!(f, "fn {}(", name)?;
+                write_separated(f, parameters, ", ")?;
+                write!(f, ") {}", body)
+            }
+            Node::FunctionLiteral { ref parameters, ref body, .. } => {
+                write!(f, "fn(")?;
+                write_separated(f, parameters, ", ")?;
+                write!(f, ") {}", body)
+            }
+            Node::CallExpression { ref fn_name, ref parameters, .. } => {
+                write!(f, "{}(", fn_name)?;
+                write_separated(f, parameters, ", ")?;
+                write!(f, ")")
+            }
+            Node::ArrayLiteral { ref elements, .. } => {
+                write!(f, "[")?;
+                write_separated(f, elements, ", ")?;
+                write!(f, "]")
+            }
+            Node::HashLiteral { ref pairs, .. } => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Node::IndexExpression { ref left, ref index, .. } => write!(f, "({}[{}])", left, index),
+            Node::AssignExpression { token, ref target, ref value, .. } => {
+                write!(f, "({} {} {})", target, token, value)
+            }
+            Node::IfExpression { ref condition, ref consequence, ref alternative, .. } => {
+                write!(f, "if ({}) {}", condition, consequence)?;
+                match *alternative {
+                    Some(ref alternative) => write!(f, " else {}", alternative),
+                    None => Ok(()),
+                }
+            }
+            Node::WhileStatement { ref condition, ref body, .. } => {
+                write!(f, "while ({}) {}", condition, body)
+            }
+            Node::ForStatement { ref init, ref condition, ref update, ref body, .. } => {
+                write!(f, "for (")?;
+                if let Some(ref init) = *init {
+                    write!(f, "{}", init.to_string().trim_end_matches(';'))?;
+                }
+                write!(f, ";")?;
+                if let Some(ref condition) = *condition {
+                    write!(f, " {}", condition)?;
+                }
+                write!(f, ";")?;
+                if let Some(ref update) = *update {
+                    write!(f, " {}", update)?;
+                }
+                write!(f, ") {}", body)
+            }
+            Node::LoopStatement { ref body, .. } => write!(f, "loop {}", body),
+            Node::BreakStatement { .. } => write!(f, "break;"),
+            Node::ContinueStatement { .. } => write!(f, "continue;"),
+        }
+    }
+}
+
+/// The left-hand side of a `let` statement, which binds one or more names.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern<'a> {
+    // Binds the whole value to an Identifier
+    Identifier(Box<Node<'a>>),
+    // `[a, b, ...rest]` matches an array element by element, and binds any
+    // elements after those to `rest` as an array
+    Array {
+        token: Token<'a>,
+        span: Span,
+        elements: Vec<Pattern<'a>>,
+        rest: Option<Box<Node<'a>>>,
+    },
+    // `{name, age}` binds each Identifier to the value of the hash's string
+    // key with the same name
+    Hash {
+        token: Token<'a>,
+        span: Span,
+        keys: Vec<Node<'a>>,
+    },
+}
+
+impl<'a> Pattern<'a> {
+    pub fn span(&self) -> Span {
+        match *self {
+            Pattern::Identifier(ref name) => name.span(),
+            Pattern::Array { span, .. } |
+            Pattern::Hash { span, .. } => span,
+        }
+    }
+
+    /// Returns the Identifier nodes the pattern binds, in source order.
+    pub fn names(&self) -> Vec<&Node<'a>> {
+        match *self {
+            Pattern::Identifier(ref name) => vec![name],
+            Pattern::Array { ref elements, ref rest, .. } => {
+                let mut names: Vec<&Node<'a>> =
+                    elements.iter().flat_map(Pattern::names).collect();
+                names.extend(rest.as_ref().map(|rest| &**rest));
+                names
+            }
+            Pattern::Hash { ref keys, .. } => keys.iter().collect(),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Pattern<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pattern::Identifier(ref name) => write!(f, "{}", name),
+            Pattern::Array { ref elements, ref rest, .. } => {
+                write!(f, "[")?;
+                write_separated(f, elements, ", ")?;
+                match *rest {
+                    Some(ref rest) if elements.is_empty() => write!(f, "...{}]", rest),
+                    Some(ref rest) => write!(f, ", ...{}]", rest),
+                    None => write!(f, "]"),
+                }
+            }
+            Pattern::Hash { ref keys, .. } => {
+                write!(f, "{{")?;
+                write_separated(f, keys, ", ")?;
+                write!(f, "}}")
+            }
+        }
+    }
 }
 
 #[derive(Default, Debug, PartialEq)]
@@ -84,11 +471,16 @@ impl<'a> Program<'a> {
         Program { statements: vec![] }
     }
 
-    fn get_token_literal(&self) -> Token {
+    fn get_token_literal(&self) -> Token<'a> {
         self.statements
-            .iter()
-            .next()
+            .first()
             .map(|n| n.get_token_literal())
             .expect("Expected a valid token")
     }
 }
+
+impl<'a> fmt::Display for Program<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_separated(f, &self.statements, "\n")
+    }
+}
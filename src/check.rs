@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+use ast::{Node, Program};
+use token::{Span, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckErrorKind {
+    // An assignment to a binding that was not declared with `let mut`
+    Immutable,
+    // An assignment to a name that no enclosing scope binds
+    Undeclared,
+    // An immutable binding of a name that a function assigns to
+    CapturedRebinding,
+}
+
+/// An assignment that could change a binding it must not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckError {
+    pub kind: CheckErrorKind,
+    pub name: String,
+    // The span of the whole assignment expression, or of the name for
+    // CapturedRebinding
+    pub span: Span,
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            CheckErrorKind::Immutable => {
+                write!(f, "cannot assign to immutable binding: {}", self.name)
+            }
+            CheckErrorKind::Undeclared => {
+                write!(f, "cannot assign to undeclared identifier: {}", self.name)
+            }
+            CheckErrorKind::CapturedRebinding => {
+                write!(f, "cannot rebind {} immutably, as a function assigns to it", self.name)
+            }
+        }
+    }
+}
+
+// What a name may be bound as at some point in a scope, with one flag for
+// each possibility
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct State {
+    unbound: bool,
+    mutable: bool,
+    immutable: bool,
+}
+
+const UNBOUND: State = State {
+    unbound: true,
+    mutable: false,
+    immutable: false,
+};
+
+impl State {
+    fn bound(mutable: bool) -> State {
+        State {
+            unbound: false,
+            mutable,
+            immutable: !mutable,
+        }
+    }
+
+    fn join(self, other: State) -> State {
+        State {
+            unbound: self.unbound || other.unbound,
+            mutable: self.mutable || other.mutable,
+            immutable: self.immutable || other.immutable,
+        }
+    }
+}
+
+// Names missing from a map of states are unbound
+type States<'a> = HashMap<&'a str, State>;
+
+// The state of each name after one of two paths through the code
+fn join_states<'a>(left: &States<'a>, right: &States<'a>) -> States<'a> {
+    let mut joined = left.clone();
+    for (name, state) in joined.iter_mut() {
+        *state = state.join(right.get(name).cloned().unwrap_or(UNBOUND));
+    }
+    for (&name, &state) in right {
+        joined.entry(name).or_insert_with(|| state.join(UNBOUND));
+    }
+    joined
+}
+
+// What is known about a name over every point of a scope
+#[derive(Debug, Clone, Copy, Default)]
+struct Facts {
+    bound: bool,
+    immutable: bool,
+    // Whether a function defined in the scope assigns to the name
+    captured: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Scope<'a> {
+    // The states at the point being checked
+    states: States<'a>,
+    facts: HashMap<&'a str, Facts>,
+    // Indexes into `Checker::captures` of the assignments that may resolve
+    // to this scope, but to names it did not bind yet
+    captures: Vec<usize>,
+}
+
+// An assignment in a function to a name that was unbound where the function
+// was defined. It is undeclared unless a scope it may resolve to binds the
+// name later on.
+#[derive(Debug)]
+struct Capture<'a> {
+    name: &'a str,
+    span: Span,
+    found: bool,
+}
+
+/// Rejects assignments that could change an immutable binding, or a name
+/// that is never bound, before a program runs.
+///
+/// Like the evaluator, the checker has one scope for the program and one for
+/// each function, with blocks and loops sharing the scope around them. Since
+/// `let` can rebind a name in the same scope, whether a name is mutable
+/// depends on the path taken to reach an assignment, so the checker follows
+/// every path through branches and loops. A function can run whenever it is
+/// called, so it may only assign to a name from an enclosing scope if every
+/// binding of that name there is mutable. Parameters and declared functions
+/// are immutable.
+///
+/// A `Checker` remembers the top-level bindings of every program it accepts,
+/// so that the REPL can check each chunk against the bindings made by
+/// earlier ones.
+#[derive(Debug)]
+pub struct Checker<'a> {
+    // The innermost scope is last
+    scopes: Vec<Scope<'a>>,
+    captures: Vec<Capture<'a>>,
+    errors: Vec<CheckError>,
+    // The top-level states to assume if the last accepted program fails
+    // part way through
+    fallback: States<'a>,
+}
+
+impl<'a> Default for Checker<'a> {
+    fn default() -> Checker<'a> {
+        Checker {
+            scopes: vec![Scope::default()],
+            captures: Vec::new(),
+            errors: Vec::new(),
+            fallback: HashMap::new(),
+        }
+    }
+}
+
+impl<'a> Checker<'a> {
+    pub fn new() -> Checker<'a> {
+        Checker::default()
+    }
+
+    pub fn check_program(&mut self, program: &Program<'a>) -> Result<(), Vec<CheckError>> {
+        let globals = self.scopes[0].clone();
+        self.check_statements(&program.statements);
+
+        let captures = mem::take(&mut self.scopes[0].captures);
+        self.resolve_captures(&self.scopes[0].facts.clone(), &captures);
+        for capture in self.captures.drain(..) {
+            if !capture.found {
+                self.errors.push(CheckError {
+                    kind: CheckErrorKind::Undeclared,
+                    name: capture.name.to_owned(),
+                    span: capture.span,
+                });
+            }
+        }
+
+        if self.errors.is_empty() {
+            let mut bindings = HashMap::new();
+            for statement in &program.statements {
+                collect_bindings(statement, &mut bindings);
+            }
+            self.fallback = join_states(&globals.states, &bindings);
+            Ok(())
+        } else {
+            // A rejected program never runs, so none of its bindings exist
+            self.scopes[0] = globals;
+            Err(mem::take(&mut self.errors))
+        }
+    }
+
+    /// Forgets which of its top-level bindings the last accepted program
+    /// made, for when it stops with an error part way through. Each name it
+    /// binds may then be bound as before, or as any of its bindings.
+    pub fn rollback(&mut self) {
+        self.scopes[0].states = self.fallback.clone();
+    }
+
+    // Declared functions are bound before any statement runs, matching the
+    // hoisting the evaluator does. Their bodies are checked there too, as
+    // they can be called from that point on.
+    fn check_statements<'n, I>(&mut self, statements: I)
+        where I: IntoIterator<Item = &'n Node<'a>> + Clone,
+              'a: 'n
+    {
+        for statement in statements.clone() {
+            if let Node::FunctionDeclaration { ref name, .. } = *statement {
+                self.declare(name, false);
+            }
+        }
+        for statement in statements.clone() {
+            if let Node::FunctionDeclaration { ref parameters, ref body, .. } = *statement {
+                self.check_function(parameters, body);
+            }
+        }
+        for statement in statements {
+            self.check(statement);
+        }
+    }
+
+    fn check(&mut self, node: &Node<'a>) {
+        match *node {
+            Node::LetStatement { mutable, ref pattern, ref value, .. } => {
+                self.check(value);
+                for name in pattern.names() {
+                    self.declare(name, mutable);
+                }
+            }
+            Node::AssignExpression { span, ref target, ref value, .. } => {
+                self.check_assign_target(target, span);
+                self.check(value);
+            }
+            // Checked where it is hoisted to
+            Node::FunctionDeclaration { .. } => {}
+            Node::FunctionLiteral { ref parameters, ref body, .. } => {
+                self.check_function(parameters, body)
+            }
+            Node::BlockStatement { ref statements, .. } => {
+                self.check_statements(statements.iter().map(|s| &**s))
+            }
+            Node::ReturnStatement { ref value, .. } => {
+                if let Some(ref value) = *value {
+                    self.check(value);
+                }
+            }
+            Node::Expression { ref value, .. } => self.check(value),
+            Node::PrefixExpression { ref right, .. } => {
+                if let Some(ref right) = *right {
+                    self.check(right);
+                }
+            }
+            Node::InfixExpression { .. } |
+            Node::CallExpression { .. } |
+            Node::IndexExpression { .. } => self.check_chain(node),
+            Node::ArrayLiteral { ref elements, .. } => {
+                for element in elements {
+                    self.check(element);
+                }
+            }
+            Node::HashLiteral { ref pairs, .. } => {
+                for (key, value) in pairs {
+                    self.check(key);
+                    self.check(value);
+                }
+            }
+            Node::IfExpression { ref condition, ref consequence, ref alternative, .. } => {
+                self.check(condition);
+                let states = self.states().clone();
+                self.check(consequence);
+                let consequence = mem::replace(self.states(), states);
+                if let Some(ref alternative) = *alternative {
+                    self.check(alternative);
+                }
+                self.join(&consequence);
+            }
+            Node::WhileStatement { ref condition, ref body, .. } => {
+                self.check_loop(&[&**condition, &**body])
+            }
+            Node::ForStatement { ref init, ref condition, ref update, ref body, .. } => {
+                if let Some(ref init) = *init {
+                    self.check(init);
+                }
+                let mut parts: Vec<&Node<'a>> = condition.iter().map(|c| &**c).collect();
+                parts.push(body);
+                parts.extend(update.iter().map(|u| &**u));
+                self.check_loop(&parts)
+            }
+            Node::LoopStatement { ref body, .. } => self.check_loop(&[&**body]),
+            Node::Identifier { .. } |
+            Node::IntegerLiteral { .. } |
+            Node::FloatLiteral { .. } |
+            Node::Boolean { .. } |
+            Node::StringLiteral { .. } |
+            Node::BreakStatement { .. } |
+            Node::ContinueStatement { .. } => {}
+            #[cfg(feature = "bigint")]
+            Node::BigIntegerLiteral { .. } => {}
+        }
+    }
+
+    // Checks a chain of infix, call and index expressions from its innermost
+    // left operand outwards, following the order they run in
+    fn check_chain(&mut self, node: &Node<'a>) {
+        let mut chain = vec![node];
+        while let Some(left) = chain[chain.len() - 1].chained_operand() {
+            chain.push(left);
+        }
+        let first = chain.pop().expect("chain without an operand");
+        self.check(first);
+
+        for link in chain.into_iter().rev() {
+            match *link {
+                Node::InfixExpression { token, right: Some(ref right), .. } => {
+                    // The right operand of `&&` and `||` may not run
+                    if let Token::AND | Token::OR = token {
+                        let states = self.states().clone();
+                        self.check(right);
+                        self.join(&states);
+                    } else {
+                        self.check(right);
+                    }
+                }
+                Node::CallExpression { ref parameters, .. } => {
+                    for parameter in parameters {
+                        self.check(parameter);
+                    }
+                }
+                Node::IndexExpression { ref index, .. } => self.check(index),
+                _ => {}
+            }
+        }
+    }
+
+    fn check_function(&mut self, parameters: &[Node<'a>], body: &Node<'a>) {
+        self.scopes.push(Scope::default());
+        for parameter in parameters {
+            self.declare(parameter, false);
+        }
+        self.check(body);
+
+        let scope = self.scopes.pop().expect("function without a scope");
+        self.resolve_captures(&scope.facts, &scope.captures);
+    }
+
+    // Checks the parts of a loop, in the order they run. Any iteration can
+    // start with the bindings the parts make in an earlier one, so they are
+    // all made possible from the start, and the loop can end after any
+    // part.
+    fn check_loop(&mut self, parts: &[&Node<'a>]) {
+        let mut bindings = HashMap::new();
+        for part in parts {
+            collect_bindings(part, &mut bindings);
+        }
+        let states = join_states(self.states(), &bindings);
+
+        *self.states() = states.clone();
+        for part in parts {
+            self.check(part);
+        }
+        *self.states() = states;
+    }
+
+    // Assigning into an element of a collection changes the binding that
+    // holds it, so the binding at the root of an index chain must be mutable
+    fn check_assign_target(&mut self, mut target: &Node<'a>, span: Span) {
+        while let Node::IndexExpression { ref left, ref index, .. } = *target {
+            self.check(index);
+            target = left;
+        }
+        match *target {
+            Node::Identifier { value, .. } => self.check_assignment(value, span),
+            ref other => self.check(other),
+        }
+    }
+
+    // Follows the scopes that an assignment to `name` may resolve to at run
+    // time, from the innermost outwards, until one of them must bind it
+    fn check_assignment(&mut self, name: &'a str, span: Span) {
+        let innermost = self.scopes.len() - 1;
+        let mut found = false;
+
+        for i in (0..innermost + 1).rev() {
+            let scope = &mut self.scopes[i];
+            let state = scope.states.get(name).cloned().unwrap_or(UNBOUND);
+            let immutable = if i == innermost {
+                state.immutable
+            } else {
+                // The assignment is in a function defined in this scope,
+                // which may run after any of the scope's bindings
+                let facts = scope.facts.entry(name).or_default();
+                facts.captured = true;
+                facts.immutable
+            };
+
+            if immutable {
+                self.errors.push(CheckError {
+                    kind: CheckErrorKind::Immutable,
+                    name: name.to_owned(),
+                    span,
+                });
+                return;
+            }
+            if !state.unbound {
+                return;
+            }
+            found = found || state.mutable;
+        }
+
+        if found {
+            return;
+        }
+        if innermost == 0 {
+            self.errors.push(CheckError {
+                kind: CheckErrorKind::Undeclared,
+                name: name.to_owned(),
+                span,
+            });
+            return;
+        }
+
+        // The enclosing scopes may still bind the name after the function
+        // is defined, so whether it is undeclared is decided as they end
+        let capture = self.captures.len();
+        self.captures.push(Capture { name, span, found });
+        for scope in &mut self.scopes[..innermost] {
+            scope.captures.push(capture);
+        }
+    }
+
+    fn resolve_captures(&mut self, facts: &HashMap<&'a str, Facts>, captures: &[usize]) {
+        for &capture in captures {
+            let capture = &mut self.captures[capture];
+            if facts.get(capture.name).is_some_and(|facts| facts.bound) {
+                capture.found = true;
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Node<'a>, mutable: bool) {
+        if let Node::Identifier { value, span, .. } = *name {
+            let scope = self.scopes.last_mut().expect("checker without a scope");
+            let facts = scope.facts.entry(value).or_default();
+            facts.bound = true;
+            if !mutable {
+                facts.immutable = true;
+                if facts.captured {
+                    self.errors.push(CheckError {
+                        kind: CheckErrorKind::CapturedRebinding,
+                        name: value.to_owned(),
+                        span,
+                    });
+                }
+            }
+            scope.states.insert(value, State::bound(mutable));
+        }
+    }
+
+    fn states(&mut self) -> &mut States<'a> {
+        &mut self.scopes.last_mut().expect("checker without a scope").states
+    }
+
+    // Merges the states of another path into the current ones
+    fn join(&mut self, other: &States<'a>) {
+        let joined = join_states(self.states(), other);
+        *self.states() = joined;
+    }
+}
+
+// Adds the bindings that `node` makes in the scope it runs in, outside of any
+// functions it defines, to `bindings`
+fn collect_bindings<'a>(node: &Node<'a>, bindings: &mut States<'a>) {
+    fn bind<'a>(bindings: &mut States<'a>, name: &Node<'a>, mutable: bool) {
+        if let Node::Identifier { value, .. } = *name {
+            let state = bindings.get(value).map_or(State::bound(mutable),
+                                                   |state| state.join(State::bound(mutable)));
+            bindings.insert(value, state);
+        }
+    }
+
+    match *node {
+        Node::LetStatement { mutable, ref pattern, ref value, .. } => {
+            collect_bindings(value, bindings);
+            for name in pattern.names() {
+                bind(bindings, name, mutable);
+            }
+        }
+        Node::FunctionDeclaration { ref name, .. } => bind(bindings, name, false),
+        Node::FunctionLiteral { .. } => {}
+        Node::BlockStatement { ref statements, .. } => {
+            for statement in statements {
+                collect_bindings(statement, bindings);
+            }
+        }
+        Node::ReturnStatement { value: Some(ref value), .. } |
+        Node::Expression { ref value, .. } |
+        Node::PrefixExpression { right: Some(ref value), .. } |
+        Node::LoopStatement { body: ref value, .. } => collect_bindings(value, bindings),
+        Node::AssignExpression { ref target, ref value, .. } => {
+            collect_bindings(target, bindings);
+            collect_bindings(value, bindings);
+        }
+        Node::InfixExpression { .. } |
+        Node::CallExpression { .. } |
+        Node::IndexExpression { .. } => {
+            // Down the chain of operators, rather than recursing into it
+            let mut link = node;
+            while let Some(left) = link.chained_operand() {
+                match *link {
+                    Node::InfixExpression { right: Some(ref right), .. } => {
+                        collect_bindings(right, bindings)
+                    }
+                    Node::CallExpression { parameters: ref values, .. } => {
+                        for value in values {
+                            collect_bindings(value, bindings);
+                        }
+                    }
+                    Node::IndexExpression { ref index, .. } => collect_bindings(index, bindings),
+                    _ => {}
+                }
+                link = left;
+            }
+            collect_bindings(link, bindings);
+        }
+        Node::ArrayLiteral { ref elements, .. } => {
+            for element in elements {
+                collect_bindings(element, bindings);
+            }
+        }
+        Node::HashLiteral { ref pairs, .. } => {
+            for (key, value) in pairs {
+                collect_bindings(key, bindings);
+                collect_bindings(value, bindings);
+            }
+        }
+        Node::IfExpression { ref condition, ref consequence, ref alternative, .. } => {
+            collect_bindings(condition, bindings);
+            collect_bindings(consequence, bindings);
+            if let Some(ref alternative) = *alternative {
+                collect_bindings(alternative, bindings);
+            }
+        }
+        Node::WhileStatement { ref condition, ref body, .. } => {
+            collect_bindings(condition, bindings);
+            collect_bindings(body, bindings);
+        }
+        Node::ForStatement { ref init, ref condition, ref update, ref body, .. } => {
+            for part in init.iter().chain(condition).chain(update) {
+                collect_bindings(part, bindings);
+            }
+            collect_bindings(body, bindings);
+        }
+        Node::ReturnStatement { value: None, .. } |
+        Node::PrefixExpression { right: None, .. } |
+        Node::Identifier { .. } |
+        Node::IntegerLiteral { .. } |
+        Node::FloatLiteral { .. } |
+        Node::Boolean { .. } |
+        Node::StringLiteral { .. } |
+        Node::BreakStatement { .. } |
+        Node::ContinueStatement { .. } => {}
+        #[cfg(feature = "bigint")]
+        Node::BigIntegerLiteral { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn check_input(input: &str) -> Result<(), Vec<CheckError>> {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        Checker::new().check_program(&program)
+    }
+
+    fn error_of(kind: CheckErrorKind, name: &str, start: usize, end: usize) -> CheckError {
+        CheckError {
+            kind,
+            name: name.to_owned(),
+            span: Span {
+                start,
+                end,
+                line: 1,
+                column: start + 1,
+            },
+        }
+    }
+
+    fn error(name: &str, start: usize, end: usize) -> CheckError {
+        error_of(CheckErrorKind::Immutable, name, start, end)
+    }
+
+    #[test]
+    fn test_mutable_bindings() {
+        let cases = vec![
+            "let mut x = 1; x = 2; x += 1;",
+            "let mut xs = [1]; xs[0] = 2;",
+            "let x = 1; let mut x = x; x = 2;",
+            "let mut n = 0; let inc = fn() { n += 1 }; inc();",
+            "let mut i = 0; while (i < 3) { i += 1; }",
+            "for (let mut i = 0; i < 3; i += 1) { }",
+            "let f = fn(x) { let mut x = x; x = 2; x };",
+            "let mut [a, {b}, ...rest] = xs; a = 1; b = 2; rest = [];",
+            // Rebinding with `let` is not an assignment
+            "let x = 1; let x = 2;",
+            // A declared function can assign to a name bound after it
+            "fn f() { x = 1 } let mut x = 0; f();",
+            "let x = 1; fn f() { let mut x = 0; let g = fn() { x = 2 }; g() }",
+            "let mut x = 1; if (true) { let mut x = 2; } else { x = 3; } x = 4;",
+            "loop { let mut y = 0; y = 1; break; }",
+        ];
+
+        for input in cases {
+            assert_eq!(check_input(input), Ok(()), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_immutable_bindings() {
+        let cases = vec![
+            ("let x = 1; x = 2;", vec![error("x", 11, 16)]),
+            ("let x = 1; x += 2;", vec![error("x", 11, 17)]),
+            ("let xs = [[1]]; xs[0][0] = 2;", vec![error("xs", 16, 28)]),
+            ("let mut x = 1; let x = x; x = 2;", vec![error("x", 26, 31)]),
+            ("let f = fn(x) { x = 2 };", vec![error("x", 16, 21)]),
+            ("let [a, ...rest] = xs; rest = [];", vec![error("rest", 23, 32)]),
+            ("fn f() { 1 } f = 2;", vec![error("f", 13, 18)]),
+            ("let x = 1; { let y = 2; loop { x = y; y = x; } }",
+             vec![error("x", 31, 36), error("y", 38, 43)]),
+            ("let x = 1; let f = fn() { let mut x = 0; x = 1; fn() { x = 2 } }; x = 3;",
+             vec![error("x", 66, 71)]),
+            // Bindings made on only one path, or in a later loop iteration
+            ("let x = 1; if (true) { let mut x = 2; } x = 3;", vec![error("x", 40, 45)]),
+            ("let mut x = 1; true || if (true) { let x = 2; true }; x = 3;",
+             vec![error("x", 54, 59)]),
+            ("let mut x = 1; loop { x = 2; let x = 3; }", vec![error("x", 22, 27)]),
+            // A function may run after any binding in its defining scope
+            ("let x = 1; let mut x = 2; let f = fn() { x = 3 };", vec![error("x", 41, 46)]),
+            ("let x = 1; let f = fn() { g(); let mut x = 0; fn g() { x = 2 } };",
+             vec![error("x", 55, 60)]),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(check_input(input), Err(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_captured_and_undeclared_bindings() {
+        use self::CheckErrorKind::*;
+
+        let cases = vec![
+            ("fn g() { x = 1 } let x = 0; g();", vec![error_of(CapturedRebinding, "x", 21, 22)]),
+            ("let mut x = 0; let f = fn() { x = 1 }; let x = 5; f();",
+             vec![error_of(CapturedRebinding, "x", 43, 44)]),
+            ("y = 1;", vec![error_of(Undeclared, "y", 0, 5)]),
+            ("fn g() { y = 1 } g();", vec![error_of(Undeclared, "y", 9, 14)]),
+            ("let f = fn() { let g = fn() { y += 1 }; g };",
+             vec![error_of(Undeclared, "y", 30, 36)]),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(check_input(input), Err(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_long_operator_chains() {
+        let input = format!("let x = 1; x{}", " + x".repeat(100_000));
+        assert_eq!(check_input(&input), Ok(()));
+        let input = format!("let xs = [[0]]; f(){}", "(xs = 1)".repeat(100_000));
+        assert_eq!(check_input(&input).unwrap_err().len(), 100_000);
+        let input = format!("let xs = [[0]]; xs{} = 1", "[0]".repeat(100_000));
+        assert_eq!(check_input(&input), Err(vec![error("xs", 16, input.len())]));
+    }
+
+    #[test]
+    fn test_bindings_persist_between_programs() {
+        let mut checker = Checker::new();
+        let mut check = |input| {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            checker.check_program(&program)
+        };
+
+        assert_eq!(check("let mut a = 1; let b = 2;"), Ok(()));
+        assert_eq!(check("a = 3;"), Ok(()));
+        assert_eq!(check("b = 3;"), Err(vec![error("b", 0, 5)]));
+        // The bindings of a rejected program are forgotten
+        assert_eq!(check("let mut b = 1; a = 1; let c = 1; c = 2;"),
+                   Err(vec![error("c", 33, 38)]));
+        assert_eq!(check("b = 3;"), Err(vec![error("b", 0, 5)]));
+        // Functions from earlier programs keep their names mutable
+        assert_eq!(check("let inc = fn() { a += 1 };"), Ok(()));
+        assert_eq!(check("let a = 0;"),
+                   Err(vec![error_of(CheckErrorKind::CapturedRebinding, "a", 4, 5)]));
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut checker = Checker::new();
+        let mut check = |input, fails| {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let result = checker.check_program(&program);
+            if fails {
+                checker.rollback();
+            }
+            result
+        };
+
+        assert_eq!(check("let mut a = 1;", false), Ok(()));
+        // Only the first binding may have been made
+        assert_eq!(check("let a = 2; let mut a = 3;", true), Ok(()));
+        assert_eq!(check("a = 4;", false), Err(vec![error("a", 0, 5)]));
+    }
+}
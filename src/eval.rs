@@ -0,0 +1,2185 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use ast::*;
+use token::{Span, Token};
+
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+#[cfg(feature = "bigint")]
+use num_traits::{Signed, ToPrimitive, Zero};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object<'a> {
+    Integer(i64),
+    // An integer outside the i64 range, with the `bigint` feature. Results
+    // that fit in an i64 are always stored as `Integer` instead.
+    #[cfg(feature = "bigint")]
+    BigInteger(BigInt),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    // Copies of a collection share its elements until one of them is changed
+    Array(Rc<Vec<Object<'a>>>),
+    Hash(Rc<BTreeMap<HashKey, Object<'a>>>),
+    Function {
+        parameters: Rc<Vec<Node<'a>>>,
+        body: Rc<Node<'a>>,
+        // The scope the function was defined in, which its body runs in
+        env: Environment<'a>,
+    },
+    // The value of statements and empty blocks. There is no null.
+    Unit,
+}
+
+impl<'a> Object<'a> {
+    // Calls `f` with the collection this value shares, or the scope of the
+    // function it is
+    fn for_each_reference(&self, f: &mut dyn FnMut(Shared<'a>)) {
+        match *self {
+            Object::Array(ref elements) => f(Shared::Array(Rc::clone(elements))),
+            Object::Hash(ref pairs) => f(Shared::Hash(Rc::clone(pairs))),
+            Object::Function { ref env, .. } => f(Shared::Scope(Rc::clone(&env.scope))),
+            _ => {}
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Object::Integer(_) => "INTEGER",
+            #[cfg(feature = "bigint")]
+            Object::BigInteger(_) => "INTEGER",
+            Object::Float(_) => "FLOAT",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::Array(_) => "ARRAY",
+            Object::Hash(_) => "HASH",
+            Object::Function { .. } => "FUNCTION",
+            Object::Unit => "UNIT",
+        }
+    }
+}
+
+impl<'a> fmt::Display for Object<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Object::Integer(i) => write!(f, "{}", i),
+            #[cfg(feature = "bigint")]
+            Object::BigInteger(ref i) => write!(f, "{}", i),
+            // Debug keeps the `.0` on whole floats so they read back as floats
+            Object::Float(x) => write!(f, "{:?}", x),
+            Object::Boolean(b) => write!(f, "{}", b),
+            Object::String(ref s) => write!(f, "{}", s),
+            Object::Array(ref elements) => {
+                write!(f, "[")?;
+                write_separated(f, elements, ", ")?;
+                write!(f, "]")
+            }
+            Object::Hash(ref pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Object::Function { ref parameters, .. } => {
+                let names: Vec<&str> = parameters.iter()
+                    .filter_map(|p| match *p {
+                        Node::Identifier { value, .. } => Some(value),
+                        _ => None,
+                    })
+                    .collect();
+                write!(f, "fn({}) {{ ... }}", names.join(", "))
+            }
+            Object::Unit => write!(f, "()"),
+        }
+    }
+}
+
+/// The values that can be used as hash keys. Keys are ordered by type, then
+/// by value, which is the order a hash prints its entries in.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HashKey {
+    Integer(i64),
+    #[cfg(feature = "bigint")]
+    BigInteger(BigInt),
+    Boolean(bool),
+    String(String),
+}
+
+impl HashKey {
+    pub fn from_object(object: Object) -> Result<HashKey, EvalErrorKind> {
+        match object {
+            Object::Integer(i) => Ok(HashKey::Integer(i)),
+            #[cfg(feature = "bigint")]
+            Object::BigInteger(i) => Ok(HashKey::BigInteger(i)),
+            Object::Boolean(b) => Ok(HashKey::Boolean(b)),
+            Object::String(s) => Ok(HashKey::String(s)),
+            other => Err(EvalErrorKind::UnhashableKey(other.type_name())),
+        }
+    }
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HashKey::Integer(i) => write!(f, "{}", i),
+            #[cfg(feature = "bigint")]
+            HashKey::BigInteger(ref i) => write!(f, "{}", i),
+            HashKey::Boolean(b) => write!(f, "{}", b),
+            HashKey::String(ref s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalErrorKind {
+    UnknownIdentifier(String),
+    UndeclaredAssignment(String),
+    UnknownOperator {
+        operator: String,
+        operand: &'static str,
+    },
+    TypeMismatch {
+        operator: String,
+        left: &'static str,
+        right: &'static str,
+    },
+    MissingOperand(String),
+    IntegerOverflow(String),
+    DivisionByZero,
+    NegativeOperand(String),
+    NotAFunction(&'static str),
+    InvalidIndex {
+        container: &'static str,
+        index: &'static str,
+    },
+    IndexOutOfRange {
+        index: String,
+        length: usize,
+    },
+    UnhashableKey(&'static str),
+    KeyNotFound(String),
+    PatternMismatch {
+        pattern: &'static str,
+        value: &'static str,
+    },
+    WrongElementCount {
+        expected: usize,
+        found: usize,
+        // Whether the pattern has a rest binding, which takes any extra elements
+        rest: bool,
+    },
+    NonBooleanCondition(&'static str),
+    LoopControlOutsideLoop,
+    WrongArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+    RecursionLimitExceeded(usize),
+    UnsupportedNode(String),
+}
+
+/// An error that stopped a program, located at the innermost node whose
+/// evaluation failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    pub kind: EvalErrorKind,
+    pub span: Span,
+}
+
+// Why the evaluation of a node stopped before producing a value. `return`,
+// `break` and `continue` travel outwards alongside errors, so that no
+// expression can mistake them for a value.
+#[derive(Debug, Clone, PartialEq)]
+enum Unwind<'a> {
+    Return(Object<'a>),
+    // The location of the `break` or `continue`, in case no loop is left to
+    // stop at
+    Break(Span),
+    Continue(Span),
+    // An error that the node it happened in has not located yet
+    Error(EvalErrorKind),
+    Located(EvalError),
+}
+
+impl<'a> From<EvalErrorKind> for Unwind<'a> {
+    fn from(error: EvalErrorKind) -> Unwind<'a> {
+        Unwind::Error(error)
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl fmt::Display for EvalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalErrorKind::UnknownIdentifier(ref name) => write!(f, "identifier not found: {}", name),
+            EvalErrorKind::UndeclaredAssignment(ref name) => {
+                write!(f, "cannot assign to undeclared identifier: {}", name)
+            }
+            EvalErrorKind::UnknownOperator { ref operator, operand } => {
+                write!(f, "unknown operator: {} {}", operator, operand)
+            }
+            EvalErrorKind::TypeMismatch { ref operator, left, right } => {
+                write!(f, "type mismatch: {} {} {}", left, operator, right)
+            }
+            EvalErrorKind::MissingOperand(ref operator) => {
+                write!(f, "missing operand for operator {}", operator)
+            }
+            EvalErrorKind::IntegerOverflow(ref operator) => {
+                write!(f, "integer overflow in operator {}", operator)
+            }
+            EvalErrorKind::DivisionByZero => write!(f, "division by zero"),
+            EvalErrorKind::NegativeOperand(ref operator) => {
+                write!(f, "negative right operand for operator {}", operator)
+            }
+            EvalErrorKind::NotAFunction(type_name) => write!(f, "not a function: {}", type_name),
+            EvalErrorKind::InvalidIndex { container, index } => {
+                write!(f, "cannot index {} with {}", container, index)
+            }
+            EvalErrorKind::IndexOutOfRange { ref index, length } => {
+                write!(f, "index {} out of range for length {}", index, length)
+            }
+            EvalErrorKind::UnhashableKey(type_name) => write!(f, "unusable as hash key: {}", type_name),
+            EvalErrorKind::KeyNotFound(ref key) => write!(f, "key not found: {}", key),
+            EvalErrorKind::PatternMismatch { pattern, value } => {
+                write!(f, "cannot destructure {} with {} pattern", value, pattern)
+            }
+            EvalErrorKind::WrongElementCount { expected, found, rest } => {
+                write!(f,
+                       "wrong number of elements: expected {}{}, found {}",
+                       if rest { "at least " } else { "" },
+                       expected,
+                       found)
+            }
+            EvalErrorKind::NonBooleanCondition(type_name) => {
+                write!(f, "condition must be a BOOLEAN, found {}", type_name)
+            }
+            EvalErrorKind::WrongArgumentCount { expected, found } => {
+                write!(f, "wrong number of arguments: expected {}, found {}", expected, found)
+            }
+            EvalErrorKind::LoopControlOutsideLoop => write!(f, "break or continue outside of a loop"),
+            EvalErrorKind::RecursionLimitExceeded(depth) => {
+                write!(f, "maximum recursion depth of {} exceeded", depth)
+            }
+            EvalErrorKind::UnsupportedNode(ref node) => write!(f, "cannot evaluate {}", node),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Scope<'a> {
+    store: HashMap<&'a str, Object<'a>>,
+    outer: Option<Environment<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    // Calls `f` with every scope and collection this one holds a reference to
+    fn for_each_reference(&self, f: &mut dyn FnMut(Shared<'a>)) {
+        if let Some(ref outer) = self.outer {
+            f(Shared::Scope(Rc::clone(&outer.scope)));
+        }
+        for value in self.store.values() {
+            value.for_each_reference(f);
+        }
+    }
+}
+
+// Something reference counted that can hold on to scopes: a scope itself, or
+// a collection that functions may be stored in
+#[derive(Clone)]
+enum Shared<'a> {
+    Scope(Rc<RefCell<Scope<'a>>>),
+    Array(Rc<Vec<Object<'a>>>),
+    Hash(Rc<BTreeMap<HashKey, Object<'a>>>),
+}
+
+impl<'a> Shared<'a> {
+    fn as_ptr(&self) -> *const () {
+        match *self {
+            Shared::Scope(ref scope) => Rc::as_ptr(scope) as *const (),
+            Shared::Array(ref elements) => Rc::as_ptr(elements) as *const (),
+            Shared::Hash(ref pairs) => Rc::as_ptr(pairs) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Shared::Scope(ref scope) => Rc::strong_count(scope),
+            Shared::Array(ref elements) => Rc::strong_count(elements),
+            Shared::Hash(ref pairs) => Rc::strong_count(pairs),
+        }
+    }
+
+    fn for_each_reference(&self, f: &mut dyn FnMut(Shared<'a>)) {
+        match *self {
+            Shared::Scope(ref scope) => scope.borrow().for_each_reference(f),
+            Shared::Array(ref elements) => {
+                for element in elements.iter() {
+                    element.for_each_reference(f);
+                }
+            }
+            Shared::Hash(ref pairs) => {
+                for value in pairs.values() {
+                    value.for_each_reference(f);
+                }
+            }
+        }
+    }
+}
+
+// The fewest scopes a program may have before they are collected
+const MIN_COLLECT_THRESHOLD: usize = 1024;
+
+// The most nodes whose evaluation may be in progress at once. Nested
+// expressions and function calls both recurse through the evaluator, and
+// this keeps that recursion within the 8 MiB stack of a main thread even in
+// unoptimized builds, where a level takes about 3 KiB, and within the 2 MiB
+// of any other thread in optimized ones.
+const MAX_EVAL_DEPTH: usize = 1000;
+
+// Every scope created by an `Environment` and the ones enclosed by it.
+//
+// Functions hold on to the scope they were defined in, so a function bound
+// in that same scope forms a reference cycle that reference counting alone
+// never frees. Collecting finds the scopes that are only kept alive by other
+// scopes and empties them, which breaks their cycles.
+#[derive(Debug)]
+struct Heap<'a> {
+    scopes: RefCell<Vec<Weak<RefCell<Scope<'a>>>>>,
+    // The number of scopes to allow before the next collection
+    threshold: Cell<usize>,
+    // The number of nodes being evaluated, up to MAX_EVAL_DEPTH
+    depth: Cell<usize>,
+}
+
+impl<'a> Default for Heap<'a> {
+    fn default() -> Heap<'a> {
+        Heap {
+            scopes: RefCell::new(Vec::new()),
+            threshold: Cell::new(MIN_COLLECT_THRESHOLD),
+            depth: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> Heap<'a> {
+    fn register(&self, scope: &Rc<RefCell<Scope<'a>>>) {
+        let len = self.scopes.borrow().len();
+        if len >= self.threshold.get() {
+            self.collect();
+            let live = self.scopes.borrow().len();
+            self.threshold.set(MIN_COLLECT_THRESHOLD.max(live * 2));
+        }
+        self.scopes.borrow_mut().push(Rc::downgrade(scope));
+    }
+
+    fn collect(&self) {
+        let mut nodes: Vec<Shared<'a>> = {
+            let mut registered = self.scopes.borrow_mut();
+            registered.retain(|scope| scope.strong_count() > 0);
+            registered.iter().filter_map(Weak::upgrade).map(Shared::Scope).collect()
+        };
+        let mut index: HashMap<*const (), usize> = nodes.iter()
+            .enumerate()
+            .map(|(i, node)| (node.as_ptr(), i))
+            .collect();
+
+        // Count the references each scope or collection gets from the others,
+        // adding the collections as they are found. Whatever else holds one,
+        // such as a value being evaluated, keeps it and everything it reaches
+        // alive.
+        let mut internal = vec![0; nodes.len()];
+        let mut i = 0;
+        while i < nodes.len() {
+            let node = nodes[i].clone();
+            node.for_each_reference(&mut |reference| {
+                let ptr = reference.as_ptr();
+                let j = match index.get(&ptr) {
+                    Some(&j) => j,
+                    // Scopes that were never registered are left alone
+                    None if matches!(reference, Shared::Scope(_)) => return,
+                    None => {
+                        index.insert(ptr, nodes.len());
+                        nodes.push(reference);
+                        internal.push(0);
+                        nodes.len() - 1
+                    }
+                };
+                internal[j] += 1;
+            });
+            i += 1;
+        }
+
+        // The handles in `nodes` account for one reference each
+        let mut stack: Vec<usize> = (0..nodes.len())
+            .filter(|&i| nodes[i].strong_count() - 1 > internal[i])
+            .collect();
+        let mut reachable = vec![false; nodes.len()];
+        while let Some(i) = stack.pop() {
+            if mem::replace(&mut reachable[i], true) {
+                continue;
+            }
+            nodes[i].for_each_reference(&mut |reference| {
+                if let Some(&j) = index.get(&reference.as_ptr()) {
+                    stack.push(j);
+                }
+            });
+        }
+
+        // Take the contents out before dropping them, so that no scope is
+        // borrowed while the values it held are freed
+        let mut garbage = Vec::new();
+        for (node, reachable) in nodes.iter().zip(reachable) {
+            if let Shared::Scope(ref scope) = *node {
+                if !reachable {
+                    garbage.push(mem::take(&mut *scope.borrow_mut()));
+                }
+            }
+        }
+        drop(garbage);
+        drop(nodes);
+
+        self.scopes.borrow_mut().retain(|scope| scope.strong_count() > 0);
+    }
+}
+
+/// A chain of scopes mapping names to values. Cloning an `Environment` is
+/// cheap and yields a handle to the same scopes.
+///
+/// Scopes that are only reachable through reference cycles between functions
+/// and the scopes they were defined in are freed every so often, as the
+/// program creates new scopes.
+#[derive(Clone, Default)]
+pub struct Environment<'a> {
+    scope: Rc<RefCell<Scope<'a>>>,
+    heap: Rc<Heap<'a>>,
+}
+
+// Printing values would recurse forever through functions that capture the
+// scope they are stored in, so only the names are shown
+impl<'a> fmt::Debug for Environment<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scope = self.scope.borrow();
+        let mut names: Vec<&str> = scope.store.keys().cloned().collect();
+        names.sort();
+        f.debug_struct("Environment")
+            .field("names", &names)
+            .field("outer", &scope.outer)
+            .finish()
+    }
+}
+
+// Two environments are equal when they are handles to the same scopes
+impl<'a> PartialEq for Environment<'a> {
+    fn eq(&self, other: &Environment<'a>) -> bool {
+        Rc::ptr_eq(&self.scope, &other.scope)
+    }
+}
+
+impl<'a> Environment<'a> {
+    pub fn new() -> Environment<'a> {
+        Environment::default()
+    }
+
+    /// Creates a new, empty scope whose lookups fall back to `outer`.
+    pub fn new_enclosed(outer: &Environment<'a>) -> Environment<'a> {
+        let env = Environment {
+            scope: Rc::new(RefCell::new(Scope {
+                store: HashMap::new(),
+                outer: Some(outer.clone()),
+            })),
+            heap: Rc::clone(&outer.heap),
+        };
+        env.heap.register(&env.scope);
+        env
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object<'a>> {
+        self.with_value(name, Object::clone)
+    }
+
+    // Calls `f` with the value of `name` in the nearest scope that binds it
+    fn with_value<T, F>(&self, name: &str, f: F) -> Option<T>
+        where F: FnOnce(&Object<'a>) -> T
+    {
+        let scope = self.scope.borrow();
+        match scope.store.get(name) {
+            Some(value) => Some(f(value)),
+            None => scope.outer.as_ref().and_then(|outer| outer.with_value(name, f)),
+        }
+    }
+
+    // Calls `f` with the value of `name` in the nearest scope that binds it,
+    // to change in place
+    fn with_slot<T, F>(&self, name: &str, f: F) -> Option<T>
+        where F: FnOnce(&mut Object<'a>) -> T
+    {
+        let mut scope = self.scope.borrow_mut();
+        if let Some(slot) = scope.store.get_mut(name) {
+            return Some(f(slot));
+        }
+        scope.outer.as_ref().and_then(|outer| outer.with_slot(name, f))
+    }
+
+    pub fn set(&self, name: &'a str, value: Object<'a>) {
+        self.scope.borrow_mut().store.insert(name, value);
+    }
+
+    /// Replaces the value of `name` in the nearest scope that binds it.
+    pub fn assign(&self, name: &str, value: Object<'a>) -> Result<(), EvalErrorKind> {
+        self.with_slot(name, |slot| *slot = value)
+            .ok_or_else(|| EvalErrorKind::UndeclaredAssignment(name.to_owned()))
+    }
+
+    pub fn eval_program(&self, program: &Program<'a>) -> Result<Object<'a>, EvalError> {
+        let mut result = Object::Unit;
+        self.hoist_declarations(&program.statements);
+
+        for statement in &program.statements {
+            result = match self.eval(statement) {
+                Ok(value) => value,
+                Err(Unwind::Return(value)) => return Ok(value),
+                Err(Unwind::Break(span)) | Err(Unwind::Continue(span)) => {
+                    return Err(EvalError {
+                        kind: EvalErrorKind::LoopControlOutsideLoop,
+                        span,
+                    })
+                }
+                Err(Unwind::Error(kind)) => {
+                    return Err(EvalError {
+                        kind,
+                        span: statement.span(),
+                    })
+                }
+                Err(Unwind::Located(error)) => return Err(error),
+            };
+        }
+
+        Ok(result)
+    }
+
+    // Evaluates `node` one level deeper into the recursion budget, which
+    // every level of nesting in a program and every call draw from. Every arm
+    // hands anything more than a line to a method of its own, which keeps the
+    // stack frame that each level of recursion takes small.
+    fn eval(&self, node: &Node<'a>) -> Result<Object<'a>, Unwind<'a>> {
+        let depth = self.heap.depth.get();
+        if depth == MAX_EVAL_DEPTH {
+            return Err(Unwind::Located(EvalError {
+                kind: EvalErrorKind::RecursionLimitExceeded(MAX_EVAL_DEPTH),
+                span: node.span(),
+            }));
+        }
+        self.heap.depth.set(depth + 1);
+
+        let result = match *node {
+            Node::IntegerLiteral { value, .. } => Ok(Object::Integer(value)),
+            #[cfg(feature = "bigint")]
+            Node::BigIntegerLiteral { ref value, .. } => Ok(big_integer(value.clone())),
+            Node::FloatLiteral { value, .. } => Ok(Object::Float(value)),
+            Node::Boolean { value, .. } => Ok(Object::Boolean(value)),
+            Node::StringLiteral { ref value, .. } => Ok(Object::String(value.clone())),
+            Node::Identifier { value, .. } => self.eval_identifier(value),
+            Node::Expression { ref value, .. } => self.eval(value),
+            Node::LetStatement { ref pattern, ref value, .. } => self.eval_let(pattern, value),
+            Node::ReturnStatement { ref value, .. } => self.eval_return(value),
+            Node::PrefixExpression { token, operator, ref right, .. } => {
+                self.eval_prefix(token, operator, right)
+            }
+            Node::InfixExpression { token, operator, ref left, ref right, .. } => {
+                self.eval_infix(token, operator, left, right)
+            }
+            Node::BlockStatement { ref statements, .. } => self.eval_block(statements),
+            Node::WhileStatement { ref condition, ref body, .. } => {
+                self.eval_while(condition, body)
+            }
+            Node::ForStatement { ref init, ref condition, ref update, ref body, .. } => {
+                self.eval_for(init, condition, update, body)
+            }
+            Node::LoopStatement { ref body, .. } => self.eval_loop(body),
+            Node::BreakStatement { span, .. } => Err(Unwind::Break(span)),
+            Node::ContinueStatement { span, .. } => Err(Unwind::Continue(span)),
+            Node::IfExpression { ref condition, ref consequence, ref alternative, .. } => {
+                self.eval_if(condition, consequence, alternative)
+            }
+            Node::FunctionDeclaration { ref name, ref parameters, ref body, .. } => {
+                self.eval_function_declaration(name, parameters, body)
+            }
+            Node::FunctionLiteral { ref parameters, ref body, .. } => {
+                Ok(self.function(parameters, body))
+            }
+            Node::ArrayLiteral { ref elements, .. } => self.eval_array(elements),
+            Node::HashLiteral { ref pairs, .. } => self.eval_hash(pairs),
+            Node::IndexExpression { ref left, ref index, .. } => self.eval_index(left, index),
+            Node::AssignExpression { token, ref target, ref value, .. } => {
+                self.eval_assign(token, target, value)
+            }
+            Node::CallExpression { ref fn_name, ref parameters, .. } => {
+                self.eval_call(fn_name, parameters)
+            }
+        };
+
+        self.heap.depth.set(depth);
+        match result {
+            Err(Unwind::Error(kind)) => {
+                Err(Unwind::Located(EvalError {
+                    kind,
+                    span: node.span(),
+                }))
+            }
+            result => result,
+        }
+    }
+
+    fn eval_identifier(&self, name: &str) -> Result<Object<'a>, Unwind<'a>> {
+        Ok(self.get(name).ok_or_else(|| EvalErrorKind::UnknownIdentifier(name.to_owned()))?)
+    }
+
+    fn eval_return(&self, value: &Option<Box<Node<'a>>>) -> Result<Object<'a>, Unwind<'a>> {
+        let value = match *value {
+            Some(ref value) => self.eval(value)?,
+            None => Object::Unit,
+        };
+        Err(Unwind::Return(value))
+    }
+
+    fn eval_let(&self, pattern: &Pattern<'a>, value: &Node<'a>) -> Result<Object<'a>, Unwind<'a>> {
+        let value = self.eval(value)?;
+
+        // Match the whole pattern before binding anything, so that a
+        // mismatch leaves no names half bound
+        let mut bindings = Vec::new();
+        destructure(pattern, value, &mut bindings)?;
+        for (name, value) in bindings {
+            self.set(name, value);
+        }
+        Ok(Object::Unit)
+    }
+
+    fn eval_prefix(&self,
+                   token: Token<'a>,
+                   operator: &str,
+                   right: &Option<Box<Node<'a>>>)
+                   -> Result<Object<'a>, Unwind<'a>> {
+        let right = match *right {
+            Some(ref right) => self.eval(right)?,
+            None => return Err(EvalErrorKind::MissingOperand(operator.to_owned()).into()),
+        };
+        Ok(eval_prefix_expression(token, operator, right)?)
+    }
+
+    fn eval_infix(&self,
+                  token: Token<'a>,
+                  operator: &str,
+                  left: &Node<'a>,
+                  right: &Option<Box<Node<'a>>>)
+                  -> Result<Object<'a>, Unwind<'a>> {
+        let left = self.eval(left)?;
+        let right = match *right {
+            Some(ref right) => right,
+            None => return Err(EvalErrorKind::MissingOperand(operator.to_owned()).into()),
+        };
+        if let Token::AND | Token::OR = token {
+            return self.eval_logical_expression(token, operator, left, right);
+        }
+        Ok(eval_infix_expression(token, operator, left, self.eval(right)?)?)
+    }
+
+    fn eval_block(&self, statements: &[Box<Node<'a>>]) -> Result<Object<'a>, Unwind<'a>> {
+        let mut result = Object::Unit;
+        self.hoist_declarations(statements.iter().map(|s| &**s));
+
+        for statement in statements {
+            result = self.eval(statement)?;
+        }
+
+        Ok(result)
+    }
+
+    fn eval_while(&self, condition: &Node<'a>, body: &Node<'a>) -> Result<Object<'a>, Unwind<'a>> {
+        while self.eval_condition(condition)? {
+            if !self.eval_loop_body(body)? {
+                break;
+            }
+        }
+        Ok(Object::Unit)
+    }
+
+    fn eval_for(&self,
+                init: &Option<Box<Node<'a>>>,
+                condition: &Option<Box<Node<'a>>>,
+                update: &Option<Box<Node<'a>>>,
+                body: &Node<'a>)
+                -> Result<Object<'a>, Unwind<'a>> {
+        if let Some(ref init) = *init {
+            self.eval(init)?;
+        }
+
+        loop {
+            if let Some(ref condition) = *condition {
+                if !self.eval_condition(condition)? {
+                    break;
+                }
+            }
+
+            if !self.eval_loop_body(body)? {
+                break;
+            }
+
+            if let Some(ref update) = *update {
+                self.eval(update)?;
+            }
+        }
+        Ok(Object::Unit)
+    }
+
+    fn eval_loop(&self, body: &Node<'a>) -> Result<Object<'a>, Unwind<'a>> {
+        while self.eval_loop_body(body)? {}
+        Ok(Object::Unit)
+    }
+
+    fn eval_if(&self,
+               condition: &Node<'a>,
+               consequence: &Node<'a>,
+               alternative: &Option<Box<Node<'a>>>)
+               -> Result<Object<'a>, Unwind<'a>> {
+        if self.eval_condition(condition)? {
+            self.eval(consequence)
+        } else {
+            match *alternative {
+                Some(ref alternative) => self.eval(alternative),
+                None => Ok(Object::Unit),
+            }
+        }
+    }
+
+    fn eval_function_declaration(&self,
+                                 name: &Node<'a>,
+                                 parameters: &Rc<Vec<Node<'a>>>,
+                                 body: &Rc<Node<'a>>)
+                                 -> Result<Object<'a>, Unwind<'a>> {
+        let function = self.function(parameters, body);
+        match *name {
+            Node::Identifier { value: name, .. } => self.set(name, function),
+            ref other => return Err(EvalErrorKind::UnsupportedNode(format!("{:?}", other)).into()),
+        }
+        Ok(Object::Unit)
+    }
+
+    fn eval_array(&self, elements: &[Node<'a>]) -> Result<Object<'a>, Unwind<'a>> {
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            values.push(self.eval(element)?);
+        }
+        Ok(Object::Array(Rc::new(values)))
+    }
+
+    fn eval_hash(&self, pairs: &[(Node<'a>, Node<'a>)]) -> Result<Object<'a>, Unwind<'a>> {
+        let mut hash = BTreeMap::new();
+        for (key, value) in pairs {
+            let key = HashKey::from_object(self.eval(key)?)?;
+            hash.insert(key, self.eval(value)?);
+        }
+        Ok(Object::Hash(Rc::new(hash)))
+    }
+
+    fn eval_index(&self, left: &Node<'a>, index: &Node<'a>) -> Result<Object<'a>, Unwind<'a>> {
+        // An element of a variable is read where the variable is stored, so
+        // the index is evaluated before the variable is looked up
+        if let Node::Identifier { value: name, .. } = *left {
+            let index = self.eval(index)?;
+            let element = self.with_value(name, |left| {
+                    eval_index_expression(left, &index).cloned()
+                })
+                .ok_or_else(|| EvalErrorKind::UnknownIdentifier(name.to_owned()))??;
+            return Ok(element);
+        }
+        let left = self.eval(left)?;
+        let index = self.eval(index)?;
+        Ok(eval_index_expression(&left, &index)?.clone())
+    }
+
+    fn eval_assign(&self,
+                   token: Token<'a>,
+                   target: &Node<'a>,
+                   value: &Node<'a>)
+                   -> Result<Object<'a>, Unwind<'a>> {
+        let (name, indexes) = self.eval_assign_target(target)?;
+        // A compound assignment reads its target before evaluating `value`
+        let compound = match compound_operator(token) {
+            Some(operator) => Some((operator, self.get_at(name, &indexes)?)),
+            None => None,
+        };
+        let value = self.eval(value)?;
+        Ok(self.assign_at(name, &indexes, compound, value)?)
+    }
+
+    // Reads the element that `indexes` lead to in the value bound to `name`
+    fn get_at(&self, name: &str, indexes: &[Object<'a>]) -> Result<Object<'a>, EvalErrorKind> {
+        self.with_value(name, |mut current| {
+                for index in indexes {
+                    current = eval_index_expression(current, index)?;
+                }
+                Ok(current.clone())
+            })
+            .ok_or_else(|| EvalErrorKind::UndeclaredAssignment(name.to_owned()))?
+    }
+
+    // Stores `value` at the element that `indexes` lead to in the value bound
+    // to `name`, first combining it with the element's previous value for a
+    // compound assignment, and returns what was stored
+    fn assign_at(&self,
+                 name: &'a str,
+                 indexes: &[Object<'a>],
+                 compound: Option<((Token<'a>, &str), Object<'a>)>,
+                 value: Object<'a>)
+                 -> Result<Object<'a>, EvalErrorKind> {
+        let value = match compound {
+            Some(((token, operator), current)) => {
+                eval_infix_expression(token, operator, current, value)?
+            }
+            None => value,
+        };
+
+        self.with_slot(name, |root| replace_at(root, indexes, value.clone()))
+            .ok_or_else(|| EvalErrorKind::UndeclaredAssignment(name.to_owned()))??;
+        Ok(value)
+    }
+
+    fn eval_call(&self, function: &Node<'a>, parameters: &[Node<'a>]) -> Result<Object<'a>, Unwind<'a>> {
+        let function = self.eval(function)?;
+
+        let mut arguments = Vec::with_capacity(parameters.len());
+        for parameter in parameters {
+            arguments.push(self.eval(parameter)?);
+        }
+
+        apply_function(function, arguments)
+    }
+
+    // A function with the given parameters and body, defined in this scope
+    fn function(&self, parameters: &Rc<Vec<Node<'a>>>, body: &Rc<Node<'a>>) -> Object<'a> {
+        Object::Function {
+            parameters: Rc::clone(parameters),
+            body: Rc::clone(body),
+            env: self.clone(),
+        }
+    }
+
+    // Evaluates `&&` or `||`, leaving the right operand unevaluated when the
+    // left one already decides the result
+    fn eval_logical_expression(&self,
+                               token: Token<'a>,
+                               operator: &str,
+                               left: Object<'a>,
+                               right: &Node<'a>)
+                               -> Result<Object<'a>, Unwind<'a>> {
+        let operand = |value: Object<'a>| match value {
+            Object::Boolean(b) => Ok(b),
+            other => {
+                Err(EvalErrorKind::UnknownOperator {
+                    operator: operator.to_owned(),
+                    operand: other.type_name(),
+                })
+            }
+        };
+
+        match (token, operand(left)?) {
+            (Token::AND, false) => Ok(Object::Boolean(false)),
+            (Token::OR, true) => Ok(Object::Boolean(true)),
+            _ => Ok(Object::Boolean(operand(self.eval(right)?)?)),
+        }
+    }
+
+    // Binds every function declared directly in `statements` before any of
+    // them run, so that declared functions can call each other in any order
+    fn hoist_declarations<'n, I>(&self, statements: I)
+        where I: IntoIterator<Item = &'n Node<'a>>,
+              'a: 'n
+    {
+        for statement in statements {
+            if let Node::FunctionDeclaration { ref name, ref parameters, ref body, .. } =
+                *statement {
+                if let Node::Identifier { value: name, .. } = **name {
+                    self.set(name, self.function(parameters, body));
+                }
+            }
+        }
+    }
+
+    // Returns the name an assignment target is rooted at, and the values of
+    // its index expressions from the outermost collection inwards
+    fn eval_assign_target(&self,
+                          target: &Node<'a>)
+                          -> Result<(&'a str, Vec<Object<'a>>), Unwind<'a>> {
+        let mut root = target;
+        let mut index_nodes = Vec::new();
+        while let Node::IndexExpression { ref left, ref index, .. } = *root {
+            index_nodes.push(index);
+            root = left;
+        }
+
+        let name = match *root {
+            Node::Identifier { value, .. } => value,
+            ref other => return Err(EvalErrorKind::UnsupportedNode(format!("{:?}", other)).into()),
+        };
+        let mut indexes = Vec::with_capacity(index_nodes.len());
+        for index in index_nodes.into_iter().rev() {
+            indexes.push(self.eval(index)?);
+        }
+        Ok((name, indexes))
+    }
+
+    fn eval_condition(&self, condition: &Node<'a>) -> Result<bool, Unwind<'a>> {
+        match self.eval(condition)? {
+            Object::Boolean(b) => Ok(b),
+            other => Err(EvalErrorKind::NonBooleanCondition(other.type_name()).into()),
+        }
+    }
+
+    // Runs one iteration of a loop. Returns whether the loop should go on,
+    // which it does unless the body executed `break`.
+    fn eval_loop_body(&self, body: &Node<'a>) -> Result<bool, Unwind<'a>> {
+        match self.eval(body) {
+            Ok(_) | Err(Unwind::Continue(_)) => Ok(true),
+            Err(Unwind::Break(_)) => Ok(false),
+            Err(other) => Err(other),
+        }
+    }
+}
+
+// Calls `function` in a new scope enclosed by the one it was defined in, so
+// its body sees its parameters, then its defining scope, and never the
+// caller's variables
+fn apply_function<'a>(function: Object<'a>,
+                      arguments: Vec<Object<'a>>)
+                      -> Result<Object<'a>, Unwind<'a>> {
+    let (parameters, body, env) = match function {
+        Object::Function { parameters, body, env } => (parameters, body, env),
+        other => return Err(EvalErrorKind::NotAFunction(other.type_name()).into()),
+    };
+
+    if parameters.len() != arguments.len() {
+        return Err(EvalErrorKind::WrongArgumentCount {
+                expected: parameters.len(),
+                found: arguments.len(),
+            }
+            .into());
+    }
+
+    let env = Environment::new_enclosed(&env);
+    for (parameter, argument) in parameters.iter().zip(arguments) {
+        match *parameter {
+            Node::Identifier { value, .. } => env.set(value, argument),
+            ref other => {
+                return Err(EvalErrorKind::UnsupportedNode(format!("{:?}", other)).into())
+            }
+        }
+    }
+
+    match env.eval(&body) {
+        Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+        Err(Unwind::Break(span)) | Err(Unwind::Continue(span)) => {
+            Err(Unwind::Located(EvalError {
+                kind: EvalErrorKind::LoopControlOutsideLoop,
+                span,
+            }))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+// Matches `value` against `pattern`, collecting the name and value of each
+// binding it makes. An array pattern needs exactly as many elements as it
+// names, or at least that many with a rest binding, and a hash pattern needs
+// every key it names.
+fn destructure<'a>(pattern: &Pattern<'a>,
+                   value: Object<'a>,
+                   bindings: &mut Vec<(&'a str, Object<'a>)>)
+                   -> Result<(), EvalErrorKind> {
+    match (pattern, value) {
+        (Pattern::Identifier(name), value) => bindings.push((binding_name(name)?, value)),
+        (Pattern::Array { elements, rest, .. }, Object::Array(values)) => {
+            let matches = match rest {
+                Some(_) => values.len() >= elements.len(),
+                None => values.len() == elements.len(),
+            };
+            if !matches {
+                return Err(EvalErrorKind::WrongElementCount {
+                    expected: elements.len(),
+                    found: values.len(),
+                    rest: rest.is_some(),
+                });
+            }
+
+            for (element, value) in elements.iter().zip(values.iter()) {
+                destructure(element, value.clone(), bindings)?;
+            }
+            if let Some(rest) = rest {
+                let rest_values = values[elements.len()..].to_vec();
+                bindings.push((binding_name(rest)?, Object::Array(Rc::new(rest_values))));
+            }
+        }
+        (Pattern::Hash { keys, .. }, Object::Hash(pairs)) => {
+            for key in keys {
+                let name = binding_name(key)?;
+                let value = pairs.get(&HashKey::String(name.to_owned()))
+                    .cloned()
+                    .ok_or_else(|| EvalErrorKind::KeyNotFound(name.to_owned()))?;
+                bindings.push((name, value));
+            }
+        }
+        (pattern, value) => {
+            return Err(EvalErrorKind::PatternMismatch {
+                pattern: match *pattern {
+                    Pattern::Hash { .. } => "HASH",
+                    _ => "ARRAY",
+                },
+                value: value.type_name(),
+            })
+        }
+    }
+    Ok(())
+}
+
+fn binding_name<'a>(name: &Node<'a>) -> Result<&'a str, EvalErrorKind> {
+    match *name {
+        Node::Identifier { value, .. } => Ok(value),
+        ref other => Err(EvalErrorKind::UnsupportedNode(format!("{:?}", other))),
+    }
+}
+
+// The operator a compound assignment applies, or None for plain `=`
+fn compound_operator<'a>(token: Token<'a>) -> Option<(Token<'a>, &'static str)> {
+    match token {
+        Token::PLUS_ASSIGN => Some((Token::PLUS, "PLUS")),
+        Token::MINUS_ASSIGN => Some((Token::MINUS, "MINUS")),
+        Token::ASTERISK_ASSIGN => Some((Token::ASTERISK, "ASTERISK")),
+        Token::SLASH_ASSIGN => Some((Token::SLASH, "SLASH")),
+        _ => None,
+    }
+}
+
+// Replaces the element at the end of the `indexes` path in `container` with
+// `value`. Array elements must already exist, and a hash gains a new key
+// only at the end of the path. Collections along the path that other values
+// share are copied before they change.
+fn replace_at<'a>(container: &mut Object<'a>,
+                  indexes: &[Object<'a>],
+                  value: Object<'a>)
+                  -> Result<(), EvalErrorKind> {
+    let (index, rest) = match indexes.split_first() {
+        Some(split) => split,
+        None => {
+            *container = value;
+            return Ok(());
+        }
+    };
+
+    match (container, index) {
+        (Object::Array(elements), &Object::Integer(i)) => {
+            let slot = usize::try_from(i)
+                .ok()
+                .filter(|&slot| slot < elements.len())
+                .ok_or_else(|| {
+                    EvalErrorKind::IndexOutOfRange {
+                        index: i.to_string(),
+                        length: elements.len(),
+                    }
+                })?;
+            replace_at(&mut Rc::make_mut(elements)[slot], rest, value)
+        }
+        #[cfg(feature = "bigint")]
+        (Object::Array(elements), Object::BigInteger(i)) => {
+            Err(EvalErrorKind::IndexOutOfRange {
+                index: i.to_string(),
+                length: elements.len(),
+            })
+        }
+        (Object::Hash(pairs), key) => {
+            let key = HashKey::from_object(key.clone())?;
+            if rest.is_empty() {
+                Rc::make_mut(pairs).insert(key, value);
+                return Ok(());
+            }
+            if !pairs.contains_key(&key) {
+                return Err(EvalErrorKind::KeyNotFound(key.to_string()));
+            }
+            let element = Rc::make_mut(pairs).get_mut(&key).expect("key was just found");
+            replace_at(element, rest, value)
+        }
+        (container, index) => {
+            Err(EvalErrorKind::InvalidIndex {
+                container: container.type_name(),
+                index: index.type_name(),
+            })
+        }
+    }
+}
+
+// Arrays are indexed from 0, and an index outside the array or a key missing
+// from a hash is an error rather than a missing value
+fn eval_index_expression<'o, 'a>(left: &'o Object<'a>,
+                                 index: &Object<'a>)
+                                 -> Result<&'o Object<'a>, EvalErrorKind> {
+    match (left, index) {
+        (Object::Array(elements), &Object::Integer(i)) => {
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| elements.get(i))
+                .ok_or_else(|| {
+                    EvalErrorKind::IndexOutOfRange {
+                        index: i.to_string(),
+                        length: elements.len(),
+                    }
+                })
+        }
+        #[cfg(feature = "bigint")]
+        (Object::Array(elements), Object::BigInteger(i)) => {
+            Err(EvalErrorKind::IndexOutOfRange {
+                index: i.to_string(),
+                length: elements.len(),
+            })
+        }
+        (Object::Hash(pairs), key) => {
+            let key = HashKey::from_object(key.clone())?;
+            pairs.get(&key).ok_or_else(|| EvalErrorKind::KeyNotFound(key.to_string()))
+        }
+        (left, index) => {
+            Err(EvalErrorKind::InvalidIndex {
+                container: left.type_name(),
+                index: index.type_name(),
+            })
+        }
+    }
+}
+
+fn eval_prefix_expression<'a>(token: Token<'a>,
+                              operator: &str,
+                              right: Object<'a>)
+                              -> Result<Object<'a>, EvalErrorKind> {
+    match (token, right) {
+        (Token::BANG, Object::Boolean(b)) => Ok(Object::Boolean(!b)),
+        // Negation overflows exactly when subtracting from zero does
+        (Token::MINUS, Object::Integer(i)) => {
+            i.checked_neg()
+                .map_or_else(|| integer_overflow(token, operator, 0, i), |i| Ok(Object::Integer(i)))
+        }
+        #[cfg(feature = "bigint")]
+        (Token::MINUS, Object::BigInteger(i)) => Ok(big_integer(-i)),
+        (Token::MINUS, Object::Float(x)) => Ok(Object::Float(-x)),
+        (Token::TILDE, Object::Integer(i)) => Ok(Object::Integer(!i)),
+        #[cfg(feature = "bigint")]
+        (Token::TILDE, Object::BigInteger(i)) => Ok(big_integer(!i)),
+        (_, right) => {
+            Err(EvalErrorKind::UnknownOperator {
+                operator: operator.to_owned(),
+                operand: right.type_name(),
+            })
+        }
+    }
+}
+
+fn eval_infix_expression<'a>(token: Token<'a>,
+                             operator: &str,
+                             left: Object<'a>,
+                             right: Object<'a>)
+                             -> Result<Object<'a>, EvalErrorKind> {
+    match (left, right) {
+        (Object::Integer(l), Object::Integer(r)) => {
+            let overflow = || integer_overflow(token, operator, l, r);
+            let integer = |i| Ok(Object::Integer(i));
+            match token {
+                Token::PLUS => l.checked_add(r).map_or_else(overflow, integer),
+                Token::MINUS => l.checked_sub(r).map_or_else(overflow, integer),
+                Token::ASTERISK => l.checked_mul(r).map_or_else(overflow, integer),
+                Token::SLASH if r == 0 => Err(EvalErrorKind::DivisionByZero),
+                // Only `i64::MIN / -1` overflows
+                Token::SLASH => l.checked_div(r).map_or_else(overflow, integer),
+                Token::PERCENT if r == 0 => Err(EvalErrorKind::DivisionByZero),
+                // The remainder takes the sign of `l`. `i64::MIN % -1` is 0,
+                // which wrapping_rem gets right.
+                Token::PERCENT => integer(l.wrapping_rem(r)),
+                Token::POWER if r < 0 => Err(EvalErrorKind::NegativeOperand(operator.to_owned())),
+                Token::POWER => {
+                    u32::try_from(r)
+                        .ok()
+                        .and_then(|r| l.checked_pow(r))
+                        .map_or_else(overflow, integer)
+                }
+                Token::AMPERSAND => integer(l & r),
+                Token::PIPE => integer(l | r),
+                Token::CARET => integer(l ^ r),
+                Token::SHL | Token::SHR if r < 0 => {
+                    Err(EvalErrorKind::NegativeOperand(operator.to_owned()))
+                }
+                // `l << r` is `l * 2 ** r`, and overflows like multiplication
+                Token::SHL if l == 0 => integer(0),
+                Token::SHL if r >= 64 => overflow(),
+                Token::SHL => i64::try_from((l as i128) << r).ok().map_or_else(overflow, integer),
+                // Shifting right by 64 or more leaves only the sign
+                Token::SHR => integer(l >> r.min(63)),
+                Token::LT => Ok(Object::Boolean(l < r)),
+                Token::GT => Ok(Object::Boolean(l > r)),
+                Token::LT_EQ => Ok(Object::Boolean(l <= r)),
+                Token::GT_EQ => Ok(Object::Boolean(l >= r)),
+                Token::EQ => Ok(Object::Boolean(l == r)),
+                Token::NOT_EQ => Ok(Object::Boolean(l != r)),
+                _ => {
+                    Err(EvalErrorKind::UnknownOperator {
+                        operator: operator.to_owned(),
+                        operand: "INTEGER",
+                    })
+                }
+            }
+        }
+        (Object::Float(l), Object::Float(r)) => eval_float_infix_expression(token, operator, l, r),
+        (Object::Integer(l), Object::Float(r)) => {
+            eval_float_infix_expression(token, operator, l as f64, r)
+        }
+        (Object::Float(l), Object::Integer(r)) => {
+            eval_float_infix_expression(token, operator, l, r as f64)
+        }
+        #[cfg(feature = "bigint")]
+        (Object::BigInteger(l), Object::BigInteger(r)) => {
+            eval_big_integer_infix_expression(token, operator, l, r)
+        }
+        #[cfg(feature = "bigint")]
+        (Object::BigInteger(l), Object::Integer(r)) => {
+            eval_big_integer_infix_expression(token, operator, l, r.into())
+        }
+        #[cfg(feature = "bigint")]
+        (Object::Integer(l), Object::BigInteger(r)) => {
+            eval_big_integer_infix_expression(token, operator, l.into(), r)
+        }
+        #[cfg(feature = "bigint")]
+        (Object::BigInteger(l), Object::Float(r)) => {
+            eval_float_infix_expression(token, operator, big_to_f64(&l), r)
+        }
+        #[cfg(feature = "bigint")]
+        (Object::Float(l), Object::BigInteger(r)) => {
+            eval_float_infix_expression(token, operator, l, big_to_f64(&r))
+        }
+        (Object::String(l), Object::String(r)) => {
+            match token {
+                Token::PLUS => Ok(Object::String(l + &r)),
+                Token::EQ => Ok(Object::Boolean(l == r)),
+                Token::NOT_EQ => Ok(Object::Boolean(l != r)),
+                _ => {
+                    Err(EvalErrorKind::UnknownOperator {
+                        operator: operator.to_owned(),
+                        operand: "STRING",
+                    })
+                }
+            }
+        }
+        (Object::Boolean(l), Object::Boolean(r)) => {
+            match token {
+                Token::EQ => Ok(Object::Boolean(l == r)),
+                Token::NOT_EQ => Ok(Object::Boolean(l != r)),
+                _ => {
+                    Err(EvalErrorKind::UnknownOperator {
+                        operator: operator.to_owned(),
+                        operand: "BOOLEAN",
+                    })
+                }
+            }
+        }
+        (left, right) => {
+            Err(EvalErrorKind::TypeMismatch {
+                operator: operator.to_owned(),
+                left: left.type_name(),
+                right: right.type_name(),
+            })
+        }
+    }
+}
+
+// Called when `l <operator> r` overflows an i64
+#[cfg(not(feature = "bigint"))]
+fn integer_overflow<'a>(_token: Token<'a>,
+                        operator: &str,
+                        _l: i64,
+                        _r: i64)
+                        -> Result<Object<'a>, EvalErrorKind> {
+    Err(EvalErrorKind::IntegerOverflow(operator.to_owned()))
+}
+
+// Called when `l <operator> r` overflows an i64, and redoes the operation
+// with arbitrary precision
+#[cfg(feature = "bigint")]
+fn integer_overflow<'a>(token: Token<'a>,
+                        operator: &str,
+                        l: i64,
+                        r: i64)
+                        -> Result<Object<'a>, EvalErrorKind> {
+    eval_big_integer_infix_expression(token, operator, l.into(), r.into())
+}
+
+// The most bits a shift or power may produce. Bigger results would take too
+// long to compute, or exhaust memory, so they are overflows.
+#[cfg(feature = "bigint")]
+const MAX_BIG_INTEGER_BITS: u64 = 1 << 20;
+
+#[cfg(feature = "bigint")]
+fn eval_big_integer_infix_expression<'a>(token: Token<'a>,
+                                         operator: &str,
+                                         l: BigInt,
+                                         r: BigInt)
+                                         -> Result<Object<'a>, EvalErrorKind> {
+    match token {
+        Token::PLUS => Ok(big_integer(l + r)),
+        Token::MINUS => Ok(big_integer(l - r)),
+        Token::ASTERISK => Ok(big_integer(l * r)),
+        Token::SLASH if r.is_zero() => Err(EvalErrorKind::DivisionByZero),
+        // Truncates toward zero, like i64 division
+        Token::SLASH => Ok(big_integer(l / r)),
+        Token::PERCENT if r.is_zero() => Err(EvalErrorKind::DivisionByZero),
+        Token::PERCENT => Ok(big_integer(l % r)),
+        Token::POWER | Token::SHL | Token::SHR if r.is_negative() => {
+            Err(EvalErrorKind::NegativeOperand(operator.to_owned()))
+        }
+        Token::POWER => {
+            // `l ** r` has at least `(l.bits() - 1) * r` bits, which is zero
+            // for 0, 1 and -1 whatever the exponent
+            let fits = |r: u32| l.bits().saturating_sub(1) * u64::from(r) <= MAX_BIG_INTEGER_BITS;
+            match r.to_u32() {
+                Some(r) if fits(r) => Ok(big_integer(l.pow(r))),
+                _ => Err(EvalErrorKind::IntegerOverflow(operator.to_owned())),
+            }
+        }
+        Token::AMPERSAND => Ok(big_integer(l & r)),
+        Token::PIPE => Ok(big_integer(l | r)),
+        Token::CARET => Ok(big_integer(l ^ r)),
+        Token::SHL if l.is_zero() => Ok(Object::Integer(0)),
+        Token::SHL => {
+            match r.to_u64() {
+                Some(r) if l.bits() + r <= MAX_BIG_INTEGER_BITS => {
+                    Ok(big_integer(l << r as usize))
+                }
+                _ => Err(EvalErrorKind::IntegerOverflow(operator.to_owned())),
+            }
+        }
+        Token::SHR => Ok(big_integer(l >> r.to_usize().unwrap_or(usize::MAX))),
+        Token::LT => Ok(Object::Boolean(l < r)),
+        Token::GT => Ok(Object::Boolean(l > r)),
+        Token::LT_EQ => Ok(Object::Boolean(l <= r)),
+        Token::GT_EQ => Ok(Object::Boolean(l >= r)),
+        Token::EQ => Ok(Object::Boolean(l == r)),
+        Token::NOT_EQ => Ok(Object::Boolean(l != r)),
+        _ => {
+            Err(EvalErrorKind::UnknownOperator {
+                operator: operator.to_owned(),
+                operand: "INTEGER",
+            })
+        }
+    }
+}
+
+// Keeps each integer in a single representation so that `Integer` and
+// `BigInteger` values never need comparing with each other
+#[cfg(feature = "bigint")]
+fn big_integer<'a>(value: BigInt) -> Object<'a> {
+    match value.to_i64() {
+        Some(i) => Object::Integer(i),
+        None => Object::BigInteger(value),
+    }
+}
+
+// num-bigint converts values too large for a float to an infinity, so the
+// conversion never actually fails
+#[cfg(feature = "bigint")]
+fn big_to_f64(value: &BigInt) -> f64 {
+    value.to_f64().unwrap_or(f64::NAN)
+}
+
+// Arithmetic with at least one FLOAT operand. The INTEGER side, if any, is
+// converted to the nearest float first, so `1 == 1.0` and `7 / 2.0 == 3.5`.
+// Unlike integer division, float division follows IEEE 754 rather than
+// failing: `1.0 / 0` is `inf`, `-1.0 / 0` is `-inf` and `0.0 / 0` is `NaN`.
+// `NaN` compares unequal to everything, itself included.
+fn eval_float_infix_expression<'a>(token: Token<'a>,
+                                   operator: &str,
+                                   l: f64,
+                                   r: f64)
+                                   -> Result<Object<'a>, EvalErrorKind> {
+    match token {
+        Token::PLUS => Ok(Object::Float(l + r)),
+        Token::MINUS => Ok(Object::Float(l - r)),
+        Token::ASTERISK => Ok(Object::Float(l * r)),
+        Token::SLASH => Ok(Object::Float(l / r)),
+        Token::PERCENT => Ok(Object::Float(l % r)),
+        Token::POWER => Ok(Object::Float(l.powf(r))),
+        Token::LT => Ok(Object::Boolean(l < r)),
+        Token::GT => Ok(Object::Boolean(l > r)),
+        Token::LT_EQ => Ok(Object::Boolean(l <= r)),
+        Token::GT_EQ => Ok(Object::Boolean(l >= r)),
+        Token::EQ => Ok(Object::Boolean(l == r)),
+        Token::NOT_EQ => Ok(Object::Boolean(l != r)),
+        _ => {
+            Err(EvalErrorKind::UnknownOperator {
+                operator: operator.to_owned(),
+                operand: "FLOAT",
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+    use std::thread;
+
+    fn eval_input(input: &str) -> Result<Object<'_>, EvalErrorKind> {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program().expect("parse errors");
+        Environment::new().eval_program(&program).map_err(|error| error.kind)
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let cases = vec![
+            ("5", 5),
+            ("-5", -5),
+            ("5 + 5 + 5 + 5 - 10", 10),
+            ("2 * 2 * 2 * 2 * 2", 32),
+            ("1 * 2 + 3", 5),
+            ("1 + 2 * 3", 7),
+            ("-50 + 100 + -50", 0),
+            ("20 + 2 * -10", 0),
+            ("50 / 2 * 2 + 10", 60),
+            ("2 * (5 + 10)", 30),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+            ("(fn(x) { x * 2 })(21)", 42),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(Object::Integer(expected)), "{}", input);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_integer_overflow() {
+        let overflow = |operator: &str| Err(EvalErrorKind::IntegerOverflow(operator.to_owned()));
+
+        assert_eq!(eval_input("-9223372036854775808"), Ok(Object::Integer(i64::MIN)));
+        assert_eq!(eval_input("9223372036854775807"), Ok(Object::Integer(i64::MAX)));
+        assert_eq!(eval_input("9223372036854775807 + 1"), overflow("PLUS"));
+        assert_eq!(eval_input("-9223372036854775808 - 1"), overflow("MINUS"));
+        assert_eq!(eval_input("4611686018427387904 * 2"), overflow("ASTERISK"));
+        assert_eq!(eval_input("-9223372036854775808 / -1"), overflow("SLASH"));
+        assert_eq!(eval_input("let min = -9223372036854775808; -min"), overflow("MINUS"));
+        assert_eq!(eval_input("-(-9223372036854775807 - 1)"), overflow("MINUS"));
+        assert_eq!(eval_input("-9223372036854775807 - 1"), Ok(Object::Integer(i64::MIN)));
+        assert_eq!(eval_input("5 / 0"), Err(EvalErrorKind::DivisionByZero));
+        assert_eq!(eval_input("2 ** 63"), overflow("POWER"));
+        assert_eq!(eval_input("2 ** 4294967296"), overflow("POWER"));
+        assert_eq!(eval_input("1 << 63"), overflow("SHL"));
+        assert_eq!(eval_input("3 << 62"), overflow("SHL"));
+        assert_eq!(eval_input("1 << 64"), overflow("SHL"));
+        assert_eq!(eval_input("-1 << 63"), Ok(Object::Integer(i64::MIN)));
+        assert_eq!(eval_input("0 << 100"), Ok(Object::Integer(0)));
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_big_integers() {
+        let big = |s: &str| Ok(Object::BigInteger(s.parse().unwrap()));
+
+        assert_eq!(eval_input("9223372036854775807 + 1"), big("9223372036854775808"));
+        assert_eq!(eval_input("-9223372036854775808 - 1"), big("-9223372036854775809"));
+        assert_eq!(eval_input("-(-9223372036854775807 - 1)"), big("9223372036854775808"));
+        assert_eq!(eval_input("-9223372036854775808 / -1"), big("9223372036854775808"));
+        assert_eq!(eval_input("0xffff_ffff_ffff_ffff_ffff"), big("1208925819614629174706175"));
+        assert_eq!(eval_input("let fact = fn(n) { if (n < 2) { 1 } else { n * fact(n - 1) } };
+                               fact(25)"),
+                   big("15511210043330985984000000"));
+
+        // Results that fit are plain integers again
+        assert_eq!(eval_input("18446744073709551616 / 4294967296"),
+                   Ok(Object::Integer(4294967296)));
+        assert_eq!(eval_input("99999999999999999999 - 99999999999999999998"),
+                   Ok(Object::Integer(1)));
+        assert_eq!(eval_input("99999999999999999999 > 5"), Ok(Object::Boolean(true)));
+        assert_eq!(eval_input("99999999999999999999 == 99_999_999_999_999_999_999"),
+                   Ok(Object::Boolean(true)));
+        assert_eq!(eval_input("18446744073709551616 * 0.5"),
+                   Ok(Object::Float(9223372036854775808.0)));
+        assert_eq!(eval_input("99999999999999999999 / 0"), Err(EvalErrorKind::DivisionByZero));
+        assert_eq!(eval_input("2 ** 100"), big("1267650600228229401496703205376"));
+        assert_eq!(eval_input("1 << 64"), big("18446744073709551616"));
+        assert_eq!(eval_input("(1 << 64) >> 1"), big("9223372036854775808"));
+        assert_eq!(eval_input("(2 ** 64 + 5) % 2 ** 64"), Ok(Object::Integer(5)));
+        assert_eq!(eval_input("~(2 ** 64)"), big("-18446744073709551617"));
+        assert_eq!(eval_input("(2 ** 64 | 1) & 3"), Ok(Object::Integer(1)));
+        assert_eq!(eval_input("-(2 ** 64) >> 1000"), Ok(Object::Integer(-1)));
+        assert_eq!(eval_input("2 ** (2 ** 64)"),
+                   Err(EvalErrorKind::IntegerOverflow("POWER".to_owned())));
+
+        // Results too big to compute in reasonable time and memory overflow
+        assert_eq!(eval_input("(1 << 1048575) >> 1048574"), Ok(Object::Integer(2)));
+        assert_eq!(eval_input("1 << 1048576"),
+                   Err(EvalErrorKind::IntegerOverflow("SHL".to_owned())));
+        assert_eq!(eval_input("1 << 9223372036854775807"),
+                   Err(EvalErrorKind::IntegerOverflow("SHL".to_owned())));
+        assert_eq!(eval_input("(2 ** 64) << 1048512"),
+                   Err(EvalErrorKind::IntegerOverflow("SHL".to_owned())));
+        assert_eq!(eval_input("0 << 9223372036854775807"), Ok(Object::Integer(0)));
+        assert_eq!(eval_input("3 ** 4294967295"),
+                   Err(EvalErrorKind::IntegerOverflow("POWER".to_owned())));
+        assert_eq!(eval_input("(2 ** 1048576) >> 1048575"), Ok(Object::Integer(2)));
+        assert_eq!(eval_input("2 ** 1048577"),
+                   Err(EvalErrorKind::IntegerOverflow("POWER".to_owned())));
+        assert_eq!(eval_input("1 ** 4294967295"), Ok(Object::Integer(1)));
+        assert_eq!(eval_input("(-1) ** 4294967295"), Ok(Object::Integer(-1)));
+    }
+
+    #[test]
+    fn test_modulo_power_and_bitwise() {
+        let cases = vec![
+            ("7 % 3", 1),
+            ("-7 % 3", -1),
+            ("7 % -3", 1),
+            ("(-9223372036854775807 - 1) % -1", 0),
+            ("2 ** 10", 1024),
+            ("2 ** 0", 1),
+            ("2 ** 3 ** 2", 512),
+            ("-2 ** 2", -4),
+            ("(-2) ** 3", -8),
+            ("6 & 3", 2),
+            ("6 | 3", 7),
+            ("6 ^ 3", 5),
+            ("~0", -1),
+            ("~5 & 0xf", 10),
+            ("1 << 10", 1024),
+            ("-16 >> 2", -4),
+            ("1 >> 100", 0),
+            ("-1 >> 100", -1),
+            ("1 + 2 << 3 & 0xff", 24),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(Object::Integer(expected)), "{}", input);
+        }
+
+        assert_eq!(eval_input("7.5 % 2"), Ok(Object::Float(1.5)));
+        assert_eq!(eval_input("2 ** -1.0"), Ok(Object::Float(0.5)));
+        assert_eq!(eval_input("9 ** 0.5"), Ok(Object::Float(3.0)));
+        assert_eq!(eval_input("5 % 0"), Err(EvalErrorKind::DivisionByZero));
+        assert_eq!(eval_input("2 ** -1"), Err(EvalErrorKind::NegativeOperand("POWER".to_owned())));
+        assert_eq!(eval_input("1 >> -1"), Err(EvalErrorKind::NegativeOperand("SHR".to_owned())));
+        assert_eq!(eval_input("1.5 & 1"),
+                   Err(EvalErrorKind::UnknownOperator {
+                       operator: "AMPERSAND".to_owned(),
+                       operand: "FLOAT",
+                   }));
+        assert_eq!(eval_input("~true"),
+                   Err(EvalErrorKind::UnknownOperator {
+                       operator: "TILDE".to_owned(),
+                       operand: "BOOLEAN",
+                   }));
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        let cases = vec![
+            ("1.5", 1.5),
+            ("-2.5e-3", -0.0025),
+            ("1e3 + 0.5", 1000.5),
+            ("7 / 2.0", 3.5),
+            ("2 * 0.25 + 1", 1.5),
+            ("1.0 / 0", f64::INFINITY),
+            ("-1 / 0.0", f64::NEG_INFINITY),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(Object::Float(expected)), "{}", input);
+        }
+
+        match eval_input("0.0 / 0") {
+            Ok(Object::Float(x)) => assert!(x.is_nan()),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(eval_input("7 / 2"), Ok(Object::Integer(3)));
+        assert_eq!(eval_input("0.1 + 0.2 - 0.3 < 1e-9"), Ok(Object::Boolean(true)));
+        assert_eq!(eval_input("1.0 / 0 > 1e300"), Ok(Object::Boolean(true)));
+        assert_eq!(eval_input("let nan = 0.0 / 0; nan == nan"), Ok(Object::Boolean(false)));
+        assert_eq!(eval_input("let nan = 0.0 / 0; nan != nan"), Ok(Object::Boolean(true)));
+        assert_eq!(eval_input("1 == 1.0"), Ok(Object::Boolean(true)));
+        assert_eq!(eval_input("2 < 2.5"), Ok(Object::Boolean(true)));
+        assert_eq!(eval_input("1.5 + true"),
+                   Err(EvalErrorKind::TypeMismatch {
+                       operator: "PLUS".to_owned(),
+                       left: "FLOAT",
+                       right: "BOOLEAN",
+                   }));
+        assert_eq!(eval_input("2.0").map(|v| v.to_string()), Ok("2.0".to_owned()));
+    }
+
+    #[test]
+    fn test_boolean_expressions() {
+        let cases = vec![
+            ("true", true),
+            ("!true", false),
+            ("!!false", false),
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 == 1", true),
+            ("1 != 1", false),
+            ("true == true", true),
+            ("true != false", true),
+            ("1 < 2 == true", true),
+            ("1 <= 1", true),
+            ("2 <= 1", false),
+            ("1 >= 2", false),
+            ("2.5 >= 2", true),
+            ("true && false", false),
+            ("true && true", true),
+            ("false || true", true),
+            ("false || false", false),
+            ("1 < 2 && 2 < 3 || false", true),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(Object::Boolean(expected)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        // The right operand would be a runtime error if it were evaluated
+        assert_eq!(eval_input("false && 1 / 0 == 1"), Ok(Object::Boolean(false)));
+        assert_eq!(eval_input("true || missing"), Ok(Object::Boolean(true)));
+
+        assert_eq!(eval_input("true && missing"),
+                   Err(EvalErrorKind::UnknownIdentifier("missing".to_owned())));
+        assert_eq!(eval_input("1 && true"),
+                   Err(EvalErrorKind::UnknownOperator {
+                       operator: "AND".to_owned(),
+                       operand: "INTEGER",
+                   }));
+        assert_eq!(eval_input("false || 1"),
+                   Err(EvalErrorKind::UnknownOperator {
+                       operator: "OR".to_owned(),
+                       operand: "INTEGER",
+                   }));
+    }
+
+    #[test]
+    fn test_strings() {
+        let cases = vec![
+            (r#""hello""#, Object::String("hello".to_owned())),
+            (r#""a\"b\n""#, Object::String("a\"b\n".to_owned())),
+            (r#"let greet = fn(name) { "hello " + name }; greet("monkey")"#,
+             Object::String("hello monkey".to_owned())),
+            (r#""a" == "a""#, Object::Boolean(true)),
+            (r#""a" != "a""#, Object::Boolean(false)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(expected), "{}", input);
+        }
+
+        assert_eq!(eval_input(r#""a" - "b""#),
+                   Err(EvalErrorKind::UnknownOperator {
+                       operator: "MINUS".to_owned(),
+                       operand: "STRING",
+                   }));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let cases = vec![
+            ("[1, 2 * 2, 3 + 3]",
+             Object::Array(Rc::new(vec![Object::Integer(1), Object::Integer(4), Object::Integer(6)]))),
+            ("[]", Object::Array(Rc::new(vec![]))),
+            ("[1, 2, 3][0]", Object::Integer(1)),
+            ("[1, 2, 3][1 + 1]", Object::Integer(3)),
+            ("let i = 0; [1][i]", Object::Integer(1)),
+            ("let xs = [1, 2, 3]; xs[0] + xs[1] + xs[2]", Object::Integer(6)),
+            ("let xs = [[1, 2], [3, 4]]; xs[1][0]", Object::Integer(3)),
+            ("let first = fn(xs) { xs[0] }; first([fn(x) { x * 2 }])(21)", Object::Integer(42)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(expected), "{}", input);
+        }
+
+        assert_eq!(eval_input(r#"[1, "two", [true]]"#).map(|v| v.to_string()),
+                   Ok("[1, two, [true]]".to_owned()));
+        assert_eq!(eval_input("[1, 2, 3][3]"),
+                   Err(EvalErrorKind::IndexOutOfRange {
+                       index: "3".to_owned(),
+                       length: 3,
+                   }));
+        assert_eq!(eval_input("[1, 2, 3][-1]"),
+                   Err(EvalErrorKind::IndexOutOfRange {
+                       index: "-1".to_owned(),
+                       length: 3,
+                   }));
+        assert_eq!(eval_input("[1][true]"),
+                   Err(EvalErrorKind::InvalidIndex {
+                       container: "ARRAY",
+                       index: "BOOLEAN",
+                   }));
+        assert_eq!(eval_input("1[0]"),
+                   Err(EvalErrorKind::InvalidIndex {
+                       container: "INTEGER",
+                       index: "INTEGER",
+                   }));
+    }
+
+    #[test]
+    fn test_hashes() {
+        let cases = vec![
+            (r#"let h = {"a": 1, 2: 3, true: 4}; h["a"] + h[2] + h[true]"#, Object::Integer(8)),
+            (r#"let key = "k"; {key + "2": 5}["k2"]"#, Object::Integer(5)),
+            (r#"{"a": 1, "a": 2}["a"]"#, Object::Integer(2)),
+            (r#"let h = {"xs": [1, 2]}; h["xs"][1]"#, Object::Integer(2)),
+            ("{}", Object::Unit),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(expected), "{}", input);
+        }
+
+        assert_eq!(eval_input(r#"let h = {"b": 2, 1: 1, false: 0, "a": 1}; h"#)
+                       .map(|v| v.to_string()),
+                   Ok("{1: 1, false: 0, a: 1, b: 2}".to_owned()));
+        assert_eq!(eval_input(r#"let h = {}; h"#), Ok(Object::Hash(Rc::new(BTreeMap::new()))));
+        assert_eq!(eval_input(r#"{"a": 1}["b"]"#), Err(EvalErrorKind::KeyNotFound("b".to_owned())));
+        assert_eq!(eval_input(r#"{"1": 1}[1]"#), Err(EvalErrorKind::KeyNotFound("1".to_owned())));
+        assert_eq!(eval_input("{fn(x) { x }: 1}"), Err(EvalErrorKind::UnhashableKey("FUNCTION")));
+        assert_eq!(eval_input("let h = {1: 1}; h[[1]]"), Err(EvalErrorKind::UnhashableKey("ARRAY")));
+        assert_eq!(eval_input("let h = {1.5: 1}; h"), Err(EvalErrorKind::UnhashableKey("FLOAT")));
+    }
+
+    #[test]
+    fn test_let_and_functions() {
+        let input = "let five = 5;
+                     let ten = 10;
+                     let add = fn(x, y) { x + y; };
+                     let result = add(five, ten);
+                     result;";
+        assert_eq!(eval_input(input), Ok(Object::Integer(15)));
+    }
+
+    #[test]
+    fn test_function_declarations() {
+        let cases = vec![
+            ("fn double(x) { x * 2 } double(21)", 42),
+            ("fn fact(n) { if (n < 2) { 1 } else { n * fact(n - 1) } } fact(5)", 120),
+            // Declarations are hoisted to the top of their program or block
+            ("let n = twice(3); fn twice(x) { x * 2 } n", 6),
+            ("fn is_even(n) { if (n == 0) { true } else { is_odd(n - 1) } }
+              fn is_odd(n) { if (n == 0) { false } else { is_even(n - 1) } }
+              if (is_even(10) && is_odd(7)) { 1 } else { 0 }",
+             1),
+            ("fn outer() { return inner() + 1; fn inner() { 41 } } outer()", 42),
+            ("fn f() { 1 } let f = 2; f", 2),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(Object::Integer(expected)), "{}", input);
+        }
+
+        assert_eq!(eval_input("fn f() { 1 }"), Ok(Object::Unit));
+        assert_eq!(eval_input("fn outer() { fn inner() { 1 } 2 } outer(); inner()"),
+                   Err(EvalErrorKind::UnknownIdentifier("inner".to_owned())));
+    }
+
+    #[test]
+    fn test_closures() {
+        let cases = vec![
+            ("let adder = fn(x) { fn(y) { x + y } }; let add2 = adder(2); add2(3)", 5),
+            ("let adder = fn(x) { fn(y) { x + y } }; adder(1)(2) + adder(10)(20)", 33),
+            // A function sees its defining scope, not its caller's
+            ("let x = 1; let f = fn() { x }; let g = fn(x) { f() }; g(100)", 1),
+            // Parameters and `let` in the body shadow the defining scope
+            ("let x = 1; let f = fn(x) { x }; f(5) + x", 6),
+            ("let x = 1; let f = fn() { let x = 2; x }; f() + x", 3),
+            // Closures see later rebinding of the variables they capture
+            ("let x = 1; let f = fn() { x }; let x = 2; f()", 2),
+            ("let compose = fn(f, g) { fn(x) { g(f(x)) } };
+              let inc = fn(x) { x + 1 };
+              let double = fn(x) { x * 2 };
+              compose(inc, double)(5)",
+             12),
+            ("fn counter() { let n = 0; fn() { n + 1 } } let c = counter(); c()", 1),
+            ("fn sum_map(n, f) { if (n == 0) { 0 } else { f(n) + sum_map(n - 1, f) } }
+              let k = 10;
+              sum_map(3, fn(x) { x * k })",
+             60),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(Object::Integer(expected)), "{}", input);
+        }
+
+        assert_eq!(eval_input("let f = fn() { y }; let g = fn(y) { f() }; g(1)"),
+                   Err(EvalErrorKind::UnknownIdentifier("y".to_owned())));
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        // Runs on a thread with the stack of a main thread, as the budget
+        // needs more than a test thread has in unoptimized builds
+        let eval_deep = |input: String| {
+            thread::Builder::new()
+                .stack_size(8 * 1024 * 1024)
+                .spawn(move || eval_input(&input).map(|value| value.to_string()))
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+        let exceeded = Err(EvalErrorKind::RecursionLimitExceeded(MAX_EVAL_DEPTH));
+
+        let count = "fn f(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }";
+        assert_eq!(eval_deep(format!("{} f(100)", count)), Ok("100".to_owned()));
+        assert_eq!(eval_deep(format!("{} f(1000)", count)), exceeded);
+        assert_eq!(eval_deep(format!("{} f(100000)", count)), exceeded);
+
+        // Nesting within each call draws from the same budget
+        let negate = |n| {
+            format!("fn f(n) {{ if (n == 0) {{ 0 }} else {{ {}f(n - 1) }} }}", "-".repeat(n))
+        };
+        assert_eq!(eval_deep(format!("{} f(10)", negate(20))), Ok("0".to_owned()));
+        assert_eq!(eval_deep(format!("{} f(999)", negate(20))), exceeded);
+        assert_eq!(eval_deep(format!("{} f(999)", negate(240))), exceeded);
+        assert_eq!(eval_deep(format!("let x = 1; {}x", "-".repeat(240))), Ok("1".to_owned()));
+        let arrays = format!("{}{}", "[".repeat(250), "]".repeat(250));
+        assert_eq!(eval_deep(arrays.clone()), Ok(arrays));
+
+        // A chain of operators nests its left operands as deeply as it is long
+        assert_eq!(eval_deep(format!("1{}", " + 1".repeat(300))), Ok("301".to_owned()));
+        assert_eq!(eval_deep(format!("1{}", " + 1".repeat(100_000))), exceeded);
+
+        // The depth goes back down as the evaluation that failed unwinds
+        let reused = thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(move || {
+                let parse = |input: &'static str| Parser::new(Lexer::new(input)).parse_program().unwrap();
+                let (define, deep, shallow) = (parse(count), parse("f(1000)"), parse("f(100)"));
+
+                let env = Environment::new();
+                env.eval_program(&define).unwrap();
+                (env.eval_program(&deep).is_err(),
+                 env.eval_program(&shallow) == Ok(Object::Integer(100)))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(reused, (true, true));
+    }
+
+    #[test]
+    fn test_unreachable_scopes_are_freed() {
+        let define = "fn outer() { fn inner() { 1 } inner() }
+                      outer();
+                      let make = fn() { let x = 5; fn() { x } };
+                      let f = make();";
+        let call = "f()";
+        let repeat = "for (let mut i = 0; i < 10000; i += 1) { outer(); }";
+        let parse = |input| Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let (define, call, repeat) = (parse(define), parse(call), parse(repeat));
+
+        let env = Environment::new();
+        env.eval_program(&define).unwrap();
+        let scopes = env.heap.scopes.borrow().clone();
+        let live = |scope: &Weak<_>| scope.upgrade().is_some();
+
+        // The scopes of calls to `outer`, `inner` and `make`. Only the call
+        // to `outer` leaves a cycle, between its scope and `inner`.
+        assert_eq!(scopes.len(), 3);
+        assert!(live(&scopes[0]));
+        assert!(!live(&scopes[1]));
+        assert!(live(&scopes[2]));
+
+        env.heap.collect();
+        assert!(!live(&scopes[0]));
+        assert!(live(&scopes[2]));
+        assert_eq!(env.eval_program(&call), Ok(Object::Integer(5)));
+
+        env.eval_program(&repeat).unwrap();
+        assert!(env.heap.scopes.borrow().iter().filter(|scope| live(scope)).count() <
+                MIN_COLLECT_THRESHOLD * 2);
+    }
+
+    #[test]
+    fn test_shared_collections_keep_scopes_alive() {
+        // The array that `make` returns is shared with its dead scope, and is
+        // only held from outside while `churn` creates enough scopes to collect
+        let input = "fn make() { let y = 5; let f = fn() { y }; let ys = [f]; ys }
+                     fn churn() { for (let mut i = 0; i < 2000; i += 1) { fn() { 1 }(); } 0 }
+                     [make(), churn()][0][0]()";
+        assert_eq!(eval_input(input), Ok(Object::Integer(5)));
+    }
+
+    #[test]
+    fn test_assignment_changes_unshared_collections_in_place() {
+        let input = "let mut xs = [[0, 0], [0]];
+                     for (let mut i = 0; i < 20000; i += 1) { xs[0][1] += i; }";
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let env = Environment::new();
+        env.eval_program(&program).unwrap();
+
+        let before = match env.get("xs") {
+            Some(Object::Array(elements)) => Rc::as_ptr(&elements),
+            other => panic!("expected an array, got {:?}", other),
+        };
+        let program = Parser::new(Lexer::new("xs[1][0] = 1; xs[0][1]")).parse_program().unwrap();
+        assert_eq!(env.eval_program(&program), Ok(Object::Integer(199990000)));
+        match env.get("xs") {
+            Some(Object::Array(elements)) => assert_eq!(Rc::as_ptr(&elements), before),
+            other => panic!("expected an array, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_assignment() {
+        let cases = vec![
+            ("let mut x = 1; x = 2; x", Object::Integer(2)),
+            ("let mut x = 1; x = x + 1", Object::Integer(2)),
+            ("let mut a = 0; let mut b = 0; a = b = 3; a + b", Object::Integer(6)),
+            ("let mut x = 10; x += 5; x -= 3; x *= 2; x /= 4; x", Object::Integer(6)),
+            ("let mut x = 1.5; x *= 2; x", Object::Float(3.0)),
+            (r#"let mut s = "a"; s += "b"; s"#, Object::String("ab".to_owned())),
+            ("let mut xs = [1, 2, 3]; xs[1] = 20; xs",
+             Object::Array(Rc::new(vec![Object::Integer(1), Object::Integer(20), Object::Integer(3)]))),
+            ("let mut xs = [[1], [2]]; xs[1][0] += 5; xs[1][0]", Object::Integer(7)),
+            (r#"let mut h = {"a": 1}; h["b"] = 2; h["a"] += 10; h["a"] + h["b"]"#,
+             Object::Integer(13)),
+            (r#"let mut h = {"xs": [1]}; h["xs"][0] = 4; h["xs"][0]"#, Object::Integer(4)),
+            // Collections are values, so assigning into a copy leaves the original alone
+            ("let a = [1]; let mut b = a; b[0] = 2; a[0] * 10 + b[0]", Object::Integer(12)),
+            ("let mut a = [[1]]; let b = a; a[0][0] = 2; a[0][0] * 10 + b[0][0]",
+             Object::Integer(21)),
+            (r#"let mut h = {"a": {"b": 1}}; let g = h; h["a"]["b"] = 2; g["a"]["b"]"#,
+             Object::Integer(1)),
+            // An assignment updates the scope the name was declared in
+            ("let mut n = 0; let inc = fn() { n += 1 }; inc(); inc(); n", Object::Integer(2)),
+            ("fn counter() { let mut n = 0; fn() { n += 1 } } let c = counter(); c(); c()",
+             Object::Integer(2)),
+            ("let x = 1; let f = fn(x) { let mut x = x; x = 5 }; f(0); x", Object::Integer(1)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(expected), "{}", input);
+        }
+
+        assert_eq!(eval_input("x = 1"),
+                   Err(EvalErrorKind::UndeclaredAssignment("x".to_owned())));
+        assert_eq!(eval_input("x += 1"),
+                   Err(EvalErrorKind::UndeclaredAssignment("x".to_owned())));
+        assert_eq!(eval_input("let f = fn() { y = 1 }; let mut y = 0; let g = fn(y) { f() }; g(2)"),
+                   Ok(Object::Integer(1)));
+        assert_eq!(eval_input("let mut xs = [1]; xs[1] = 2"),
+                   Err(EvalErrorKind::IndexOutOfRange {
+                       index: "1".to_owned(),
+                       length: 1,
+                   }));
+        assert_eq!(eval_input(r#"let mut h = {}; h["a"][0] = 1"#),
+                   Err(EvalErrorKind::KeyNotFound("a".to_owned())));
+        assert_eq!(eval_input("let mut x = 1; x[0] = 2"),
+                   Err(EvalErrorKind::InvalidIndex {
+                       container: "INTEGER",
+                       index: "INTEGER",
+                   }));
+        assert_eq!(eval_input(r#"let mut x = 1; x -= "a""#),
+                   Err(EvalErrorKind::TypeMismatch {
+                       operator: "MINUS".to_owned(),
+                       left: "INTEGER",
+                       right: "STRING",
+                   }));
+    }
+
+    #[test]
+    fn test_destructuring() {
+        let cases = vec![
+            ("let [a, b] = [1, 2]; a * 10 + b", Object::Integer(12)),
+            ("let [a, ...rest] = [1, 2, 3]; rest",
+             Object::Array(Rc::new(vec![Object::Integer(2), Object::Integer(3)]))),
+            ("let [a, b, ...rest] = [1, 2]; rest", Object::Array(Rc::new(vec![]))),
+            ("let [[a, b], c] = [[1, 2], 3]; a + b + c", Object::Integer(6)),
+            ("let [f, x] = [fn(n) { n * 2 }, 21]; f(x)", Object::Integer(42)),
+            (r#"let {name, age} = {"name": "monkey", "age": 3, "legs": 4}; name"#,
+             Object::String("monkey".to_owned())),
+            (r#"let [{x}, ...others] = [{"x": 1}, 2]; x + others[0]"#, Object::Integer(3)),
+            ("fn div_mod(a, b) { [a / b, a % b] } let [q, r] = div_mod(17, 5); q * 10 + r",
+             Object::Integer(32)),
+            ("let mut [a, b] = [1, 2]; a = b; a", Object::Integer(2)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(expected), "{}", input);
+        }
+
+        assert_eq!(eval_input("let [a, b] = [1, 2, 3]"),
+                   Err(EvalErrorKind::WrongElementCount {
+                       expected: 2,
+                       found: 3,
+                       rest: false,
+                   }));
+        assert_eq!(eval_input("let [a, b, ...rest] = [1]"),
+                   Err(EvalErrorKind::WrongElementCount {
+                       expected: 2,
+                       found: 1,
+                       rest: true,
+                   }));
+        assert_eq!(eval_input("let [a] = 1"),
+                   Err(EvalErrorKind::PatternMismatch {
+                       pattern: "ARRAY",
+                       value: "INTEGER",
+                   }));
+        assert_eq!(eval_input("let {a} = [1]"),
+                   Err(EvalErrorKind::PatternMismatch {
+                       pattern: "HASH",
+                       value: "ARRAY",
+                   }));
+        assert_eq!(eval_input(r#"let {name, age} = {"name": "monkey"}"#),
+                   Err(EvalErrorKind::KeyNotFound("age".to_owned())));
+        // A failed match binds none of the names
+        assert_eq!(eval_input(r#"let a = 0; let [a, {b}] = [1, {}]; a"#),
+                   Err(EvalErrorKind::KeyNotFound("b".to_owned())));
+        assert_eq!(eval_input("let a = 0; let [a, b] = [1]; a"),
+                   Err(EvalErrorKind::WrongElementCount {
+                       expected: 2,
+                       found: 1,
+                       rest: false,
+                   }));
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let cases = vec![
+            ("return 10; 9;", 10),
+            ("9; return 2 * 5; 9;", 10),
+            ("let f = fn() { return 1; 2; }; f() + 1;", 2),
+            ("{ { return 10; } return 1; }", 10),
+            // A return inside an expression leaves the function, rather than
+            // becoming the value of the expression
+            ("let f = fn() { let x = if (true) { return 1 } else { 2 }; x + 10 }; f()", 1),
+            ("let f = fn() { [if (true) { return 3 } else { 0 }, 4] }; f()", 3),
+            ("let f = fn() { -if (true) { return 5 } else { 0 } }; f()", 5),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(Object::Integer(expected)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_if_else_expressions() {
+        let cases = vec![
+            ("if (true) { 10 }", Object::Integer(10)),
+            ("if (false) { 10 }", Object::Unit),
+            ("if (1 < 2) { 10 } else { 20 }", Object::Integer(10)),
+            ("if (1 > 2) { 10 } else { 20 }", Object::Integer(20)),
+            ("if (1 > 2) { 10 } else if (1 == 1) { 30 } else { 20 }", Object::Integer(30)),
+            ("let f = fn(x) { if (x > 5) { return 1; } 0 }; f(10)", Object::Integer(1)),
+            ("let f = fn(x) { if (x > 5) { return 1; } 0 }; f(2)", Object::Integer(0)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(expected), "{}", input);
+        }
+
+        assert_eq!(eval_input("if (1) { 10 }"),
+                   Err(EvalErrorKind::NonBooleanCondition("INTEGER")));
+    }
+
+    #[test]
+    fn test_loops() {
+        let cases = vec![
+            ("let mut i = 0; while (i < 5) { i += 1; } i", 5),
+            ("let mut i = 0; loop { i = i + 1; if (i == 3) { break; } } i", 3),
+            ("let mut sum = 0; for (let mut i = 0; i < 5; i += 1) { sum += i; } sum", 10),
+            ("let mut sum = 0;
+              for (let mut i = 1; i <= 6; i += 1) {
+                  if (i == 2) { continue; }
+                  sum += i;
+              }
+              sum",
+             19),
+            ("let f = fn() { loop { return 7; } }; f()", 7),
+            ("let f = fn() { for (;;) { while (true) { return 8; } } }; f()", 8),
+            ("let mut n = 0; loop { loop { break; } n += 1; if (n > 2) { break } } n", 3),
+            ("let mut n = 0; loop { n += if (n > 2) { break } else { 1 }; } n", 3),
+            ("let mut n = 0; loop { let step = if (n < 3) { 1 } else { break }; n += step; } n",
+             3),
+            ("let mut sum = 0;
+              for (let mut i = 0; i < 5; i += 1) {
+                  sum += if (i == 2) { continue } else { i };
+              }
+              sum",
+             8),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(eval_input(input), Ok(Object::Integer(expected)), "{}", input);
+        }
+
+        assert_eq!(eval_input("while (false) { 1 }"), Ok(Object::Unit));
+        assert_eq!(eval_input("while (1) { 1 }"),
+                   Err(EvalErrorKind::NonBooleanCondition("INTEGER")));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(eval_input("foo"),
+                   Err(EvalErrorKind::UnknownIdentifier("foo".to_owned())));
+        assert_eq!(eval_input("5 + true"),
+                   Err(EvalErrorKind::TypeMismatch {
+                       operator: "PLUS".to_owned(),
+                       left: "INTEGER",
+                       right: "BOOLEAN",
+                   }));
+        assert_eq!(eval_input("-true"),
+                   Err(EvalErrorKind::UnknownOperator {
+                       operator: "MINUS".to_owned(),
+                       operand: "BOOLEAN",
+                   }));
+        assert_eq!(eval_input("1 / 0"), Err(EvalErrorKind::DivisionByZero));
+        assert_eq!(eval_input("let f = fn(x) { x }; f(1, 2)"),
+                   Err(EvalErrorKind::WrongArgumentCount {
+                       expected: 1,
+                       found: 2,
+                   }));
+        assert_eq!(eval_input("5(1)"), Err(EvalErrorKind::NotAFunction("INTEGER")));
+    }
+
+    #[test]
+    fn test_error_locations() {
+        // The line and column of the innermost node that failed
+        let cases = vec![
+            ("foo", (1, 1)),
+            ("let x = 1;\nlet y = x + true;", (2, 9)),
+            ("let f = fn(x) {\n  x / 0\n};\nf(1) + 1", (2, 3)),
+            ("let xs = [1];\n[xs[0], xs[1]]", (2, 9)),
+            ("let h = {\"a\": 1};\nif (true) { h[\"b\"] }", (2, 13)),
+            ("1 + [1, 2](3)", (1, 5)),
+        ];
+
+        for (input, expected) in cases {
+            let program = Parser::new(Lexer::new(input)).parse_program().expect("parse errors");
+            let span = Environment::new().eval_program(&program).unwrap_err().span;
+            assert_eq!((span.line, span.column), expected, "{}", input);
+        }
+    }
+}
@@ -4,36 +4,115 @@
 
 // use self::regex::Regex;
 
+use std::fmt;
 use std::iter::*;
 use std::str::*;
 
+use unicode_xid::UnicodeXID;
+
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+
 use token::Token::*;
-use token::Token;
+use token::{Span, SpannedToken, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LexError {
+    UnexpectedChar(char),
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidEscape(char),
+    InvalidUnicodeEscape,
+    IntegerOverflow,
+    FloatOverflow,
+    InvalidDigit(char),
+    MissingDigits,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexError::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            LexError::UnterminatedString => write!(f, "unterminated string literal"),
+            LexError::UnterminatedComment => write!(f, "unterminated block comment"),
+            LexError::InvalidEscape(c) => write!(f, "invalid escape sequence \\{}", c),
+            LexError::InvalidUnicodeEscape => write!(f, "invalid unicode escape sequence"),
+            LexError::IntegerOverflow => {
+                write!(f, "integer literal is larger than {}", i64::MAX)
+            }
+            LexError::FloatOverflow => write!(f, "float literal is too large to represent"),
+            LexError::InvalidDigit(c) => write!(f, "invalid digit {:?} in number literal", c),
+            LexError::MissingDigits => write!(f, "number literal has no digits"),
+        }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Lexer<'a> {
     input: &'a str,
-    input_iter: Peekable<Chars<'a>>,
+    input_iter: Peekable<CharIndices<'a>>,
+    // Both positions are byte offsets into input
     position: usize, // current position in input (points to current char)
     read_position: usize, // current reading position in input (after current char)
+    line: usize, // line of the next char to be read
+    column: usize, // column of the next char to be read
+    keep_comments: bool,
 }
 
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Lexer<'a> {
         Lexer {
-            input: input,
-            input_iter: input.chars().peekable(),
+            input,
+            input_iter: input.char_indices().peekable(),
             position: 0,
-            read_position: 1,
+            read_position: 0,
+            line: 1,
+            column: 1,
+            keep_comments: false,
         }
     }
 
-    pub fn next_token<'b>(&mut self) -> Option<Token<'b>>
+    /// Creates a lexer that emits comments as `COMMENT` tokens instead of
+    /// skipping them, for tools such as formatters. The parser does not
+    /// accept these tokens.
+    pub fn with_comments(input: &'a str) -> Lexer<'a> {
+        Lexer {
+            keep_comments: true,
+            ..Lexer::new(input)
+        }
+    }
+
+    pub fn next_token<'b>(&mut self) -> Option<SpannedToken<'b>>
         where 'a: 'b
     {
+        loop {
+            self.skip_whitespace();
 
-        self.skip_whitespace();
+            let (start, line, column) = (self.read_position, self.line, self.column);
+            let token = self.read_token()?;
+
+            if let COMMENT(_) = token {
+                if !self.keep_comments {
+                    continue;
+                }
+            }
+
+            return Some(SpannedToken {
+                token,
+                span: Span {
+                    start,
+                    end: self.read_position,
+                    line,
+                    column,
+                },
+            });
+        }
+    }
+
+    fn read_token<'b>(&mut self) -> Option<Token<'b>>
+        where 'a: 'b
+    {
         if let Some(tok) = self.read_char() {
             match tok {
                 '=' => {
@@ -45,15 +124,84 @@ impl<'a> Lexer<'a> {
                     }
                 }
                 ';' => Some(SEMICOLON),
+                ':' => Some(COLON),
+                '.' => {
+                    if self.peek_char() == Some('.') && self.peek_nth_char(1) == Some('.') {
+                        self.read_char();
+                        self.read_char();
+                        Some(ELLIPSIS)
+                    } else {
+                        Some(ILLEGAL(LexError::UnexpectedChar('.')))
+                    }
+                }
                 '(' => Some(LPAREN),
                 ')' => Some(RPAREN),
                 ',' => Some(COMMA),
-                '+' => Some(PLUS),
+                '+' => {
+                    if let Some('=') = self.peek_char() {
+                        self.read_char();
+                        Some(PLUS_ASSIGN)
+                    } else {
+                        Some(PLUS)
+                    }
+                }
                 '{' => Some(LBRACE),
                 '}' => Some(RBRACE),
-                '>' => Some(GT),
-                '<' => Some(LT),
-                '-' => Some(MINUS),
+                '[' => Some(LBRACKET),
+                ']' => Some(RBRACKET),
+                '>' => {
+                    match self.peek_char() {
+                        Some('=') => {
+                            self.read_char();
+                            Some(GT_EQ)
+                        }
+                        Some('>') => {
+                            self.read_char();
+                            Some(SHR)
+                        }
+                        _ => Some(GT),
+                    }
+                }
+                '<' => {
+                    match self.peek_char() {
+                        Some('=') => {
+                            self.read_char();
+                            Some(LT_EQ)
+                        }
+                        Some('<') => {
+                            self.read_char();
+                            Some(SHL)
+                        }
+                        _ => Some(LT),
+                    }
+                }
+                '&' => {
+                    if let Some('&') = self.peek_char() {
+                        self.read_char();
+                        Some(AND)
+                    } else {
+                        Some(AMPERSAND)
+                    }
+                }
+                '|' => {
+                    if let Some('|') = self.peek_char() {
+                        self.read_char();
+                        Some(OR)
+                    } else {
+                        Some(PIPE)
+                    }
+                }
+                '^' => Some(CARET),
+                '~' => Some(TILDE),
+                '%' => Some(PERCENT),
+                '-' => {
+                    if let Some('=') = self.peek_char() {
+                        self.read_char();
+                        Some(MINUS_ASSIGN)
+                    } else {
+                        Some(MINUS)
+                    }
+                }
                 '!' => {
                     if let Some('=') = self.peek_char() {
                         self.read_char();
@@ -62,17 +210,38 @@ impl<'a> Lexer<'a> {
                         Some(BANG)
                     }
                 }
-                '*' => Some(ASTERISK),
-                '/' => Some(SLASH),
-                c if c.is_alphabetic() => {
+                '*' => {
+                    match self.peek_char() {
+                        Some('*') => {
+                            self.read_char();
+                            Some(POWER)
+                        }
+                        Some('=') => {
+                            self.read_char();
+                            Some(ASTERISK_ASSIGN)
+                        }
+                        _ => Some(ASTERISK),
+                    }
+                }
+                '/' => {
+                    match self.peek_char() {
+                        Some('/') => Some(self.read_line_comment()),
+                        Some('*') => Some(self.read_block_comment()),
+                        Some('=') => {
+                            self.read_char();
+                            Some(SLASH_ASSIGN)
+                        }
+                        _ => Some(SLASH),
+                    }
+                }
+                '"' => Some(self.read_string()),
+                c if Lexer::valid_identifier_start(c) => {
                     let (ix, end_ix) = self.read_identifier();
                     let ident = &self.input[ix..end_ix];
-                    return Some(determine_ident(ident));
+                    Some(Token::lookup_ident(ident))
                 }
-                c if c.is_digit(10) => {
-                    return Some(INT(self.read_number()));
-                }
-                _ => Some(ILLEGAL),
+                c if c.is_ascii_digit() => Some(self.read_number()),
+                c => Some(ILLEGAL(LexError::UnexpectedChar(c))),
             }
         } else {
             None
@@ -90,78 +259,289 @@ impl<'a> Lexer<'a> {
     }
 
     fn peek_char(&mut self) -> Option<char> {
-        self.input_iter.peek().map(|c| *c)
+        self.input_iter.peek().map(|&(_, c)| c)
+    }
+
+    // Looks `n` chars past the next one without consuming anything
+    fn peek_nth_char(&self, n: usize) -> Option<char> {
+        self.input[self.read_position..].chars().nth(n)
+    }
+
+    // Reads from the opening `//` up to, but not including, the end of the line
+    fn read_line_comment<'b>(&mut self) -> Token<'b>
+        where 'a: 'b
+    {
+        let start = self.position;
+        while let Some(c) = self.peek_char() {
+            if c == '\n' {
+                break;
+            }
+            self.read_char();
+        }
+        COMMENT(&self.input[start..self.read_position])
+    }
+
+    // Reads from the opening `/*` through the matching `*/`. Block comments nest.
+    fn read_block_comment<'b>(&mut self) -> Token<'b>
+        where 'a: 'b
+    {
+        let start = self.position;
+        self.read_char();
+
+        let mut depth = 1;
+        while depth > 0 {
+            match (self.read_char(), self.peek_char()) {
+                (Some('/'), Some('*')) => {
+                    self.read_char();
+                    depth += 1;
+                }
+                (Some('*'), Some('/')) => {
+                    self.read_char();
+                    depth -= 1;
+                }
+                (Some(_), _) => {}
+                (None, _) => return ILLEGAL(LexError::UnterminatedComment),
+            }
+        }
+        COMMENT(&self.input[start..self.read_position])
     }
 
     fn read_char(&mut self) -> Option<char> {
-        self.position = self.read_position;
-        self.read_position += 1;
-        self.input_iter.next()
+        let (ix, c) = self.input_iter.next()?;
+        self.position = ix;
+        self.read_position = ix + c.len_utf8();
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
     }
 
     fn read_identifier(&mut self) -> (usize, usize) {
-        let position = self.position - 1;
-        let mut end_position = position + 1;
+        let position = self.position;
         while let Some(c) = self.peek_char() {
             if Lexer::valid_identifier(c) {
-                end_position += 1;
                 self.read_char();
             } else {
                 break;
             }
         }
-        (position, end_position)
+        (position, self.read_position)
+    }
+
+    // Identifiers are a letter (XID_Start) or `_`, followed by any number of
+    // letters, digits and underscores (XID_Continue). Keywords are reserved.
+    fn valid_identifier_start(ch: char) -> bool {
+        ch == '_' || ch.is_xid_start()
     }
 
     fn valid_identifier(ch: char) -> bool {
-        ch.is_alphabetic() || ch == '_'
+        ch.is_xid_continue()
     }
 
-    fn read_number(&mut self) -> u64 {
-        let position = self.position;
+    // Reads up to and including the closing quote. The token holds the raw
+    // text between the quotes; escapes are checked here but only expanded by
+    // `unescape` once the parser builds the literal.
+    fn read_string<'b>(&mut self) -> Token<'b>
+        where 'a: 'b
+    {
+        let start = self.read_position;
+        loop {
+            match self.read_char() {
+                Some('"') => break,
+                Some('\\') => {
+                    if self.read_char().is_none() {
+                        return ILLEGAL(LexError::UnterminatedString);
+                    }
+                }
+                Some(_) => {}
+                None => return ILLEGAL(LexError::UnterminatedString),
+            }
+        }
+
+        let raw = &self.input[start..self.position];
+        match unescape(raw) {
+            Ok(_) => STRING(raw),
+            Err(e) => ILLEGAL(e),
+        }
+    }
+
+    // Reads a number literal: a decimal integer or float, or a hexadecimal,
+    // octal or binary integer with a `0x`, `0o` or `0b` prefix. `_` may
+    // separate digits anywhere after the first digit or the prefix.
+    fn read_number<'b>(&mut self) -> Token<'b>
+        where 'a: 'b
+    {
+        let start = self.position;
+        let radix = match (&self.input[self.position..self.read_position], self.peek_char()) {
+            ("0", Some('x')) => 16,
+            ("0", Some('o')) => 8,
+            ("0", Some('b')) => 2,
+            _ => 10,
+        };
+
+        if radix == 10 {
+            return self.read_decimal();
+        }
+
+        self.read_char();
+        let digits_start = self.read_position;
+        // Consume every decimal (or, for hex, hexadecimal) digit so that a
+        // digit outside the radix is reported rather than starting a new token
+        self.read_digits(radix.max(10));
+
+        let digits = strip_separators(&self.input[digits_start..self.read_position]);
+        if digits.is_empty() {
+            return ILLEGAL(LexError::MissingDigits);
+        }
+        if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+            return ILLEGAL(LexError::InvalidDigit(c));
+        }
+
+        match u64::from_str_radix(&digits, radix) {
+            Ok(value) => INT(value),
+            Err(_) => self.integer_overflow(start),
+        }
+    }
+
+    // Reads a decimal literal. A `.` only starts a fraction when a digit
+    // follows it, and `e` only starts an exponent when digits (optionally
+    // signed) follow it, so `1.foo` and `1e` still lex as separate tokens.
+    fn read_decimal<'b>(&mut self) -> Token<'b>
+        where 'a: 'b
+    {
+        let start = self.position;
+        let mut is_float = false;
+        self.read_digits(10);
+
+        let fraction_follows = self.peek_nth_char(1).is_some_and(|c| c.is_ascii_digit());
+        if self.peek_char() == Some('.') && fraction_follows {
+            is_float = true;
+            self.read_char();
+            self.read_digits(10);
+        }
+
+        if let Some('e') | Some('E') = self.peek_char() {
+            let sign = match self.peek_nth_char(1) {
+                Some('+') | Some('-') => 1,
+                _ => 0,
+            };
+            if self.peek_nth_char(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                is_float = true;
+                for _ in 0..1 + sign {
+                    self.read_char();
+                }
+                self.read_digits(10);
+            }
+        }
+
+        let digits = strip_separators(&self.input[start..self.read_position]);
+        if !is_float {
+            return match digits.parse() {
+                Ok(value) => INT(value),
+                Err(_) => self.integer_overflow(start),
+            };
+        }
+
+        match digits.parse::<f64>() {
+            Ok(value) if value.is_finite() => FLOAT(value),
+            _ => ILLEGAL(LexError::FloatOverflow),
+        }
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    fn integer_overflow<'b>(&self, _start: usize) -> Token<'b> {
+        ILLEGAL(LexError::IntegerOverflow)
+    }
+
+    // Integer literals too large for a u64 are passed on as source text, to be
+    // converted by `parse_big_integer`
+    #[cfg(feature = "bigint")]
+    fn integer_overflow<'b>(&self, start: usize) -> Token<'b>
+        where 'a: 'b
+    {
+        BIG_INT(&self.input[start..self.read_position])
+    }
+
+    fn read_digits(&mut self, radix: u32) {
         while let Some(c) = self.peek_char() {
-            if c.is_digit(10) {
+            if c.is_digit(radix) || c == '_' {
                 self.read_char();
             } else {
                 break;
             }
         }
-        self.input[position - 1..self.position]
-            .parse::<u64>()
-            .expect(&format!("Failed to parse number {}",
-                             &self.input[position - 1..self.position + 1]))
     }
 }
 
-fn determine_ident(ident: &str) -> Token {
-    if ident == "let" {
-        LET
-    } else if ident == "fn" {
-        FUNCTION
-    } else if ident == "while" {
-        WHILE
-    } else if ident == "for" {
-        FOR
-    } else if ident == "loop" {
-        LOOP
-    } else if ident == "true" {
-        TRUE
-    } else if ident == "false" {
-        FALSE
-    } else if ident == "if" {
-        IF
-    } else if ident == "else" {
-        ELSE
-    } else if ident == "return" {
-        RETURN
-    } else {
-        IDENT(ident)
+fn strip_separators(digits: &str) -> String {
+    digits.chars().filter(|&c| c != '_').collect()
+}
+
+/// Converts the source text of a `BIG_INT` literal, including any radix
+/// prefix and `_` separators, to its value.
+#[cfg(feature = "bigint")]
+pub fn parse_big_integer(raw: &str) -> Option<BigInt> {
+    let digits = strip_separators(raw);
+    let (digits, radix) = match digits.get(..2) {
+        Some("0x") => (&digits[2..], 16),
+        Some("0o") => (&digits[2..], 8),
+        Some("0b") => (&digits[2..], 2),
+        _ => (&digits[..], 10),
+    };
+    BigInt::parse_bytes(digits.as_bytes(), radix)
+}
+
+/// Expands the escape sequences `\n`, `\t`, `\"`, `\\` and `\u{...}` in the
+/// raw contents of a string literal.
+pub fn unescape(raw: &str) -> Result<String, LexError> {
+    let mut value = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('"') => value.push('"'),
+            Some('\\') => value.push('\\'),
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err(LexError::InvalidUnicodeEscape);
+                }
+                let mut digits = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(d) if d.is_ascii_hexdigit() && digits.len() < 6 => digits.push(d),
+                        _ => return Err(LexError::InvalidUnicodeEscape),
+                    }
+                }
+                let c = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(::std::char::from_u32)
+                    .ok_or(LexError::InvalidUnicodeEscape)?;
+                value.push(c);
+            }
+            Some(c) => return Err(LexError::InvalidEscape(c)),
+            None => return Err(LexError::UnterminatedString),
+        }
     }
+
+    Ok(value)
 }
 
 impl<'a> Iterator for Lexer<'a> {
-    type Item = Token<'a>;
-    fn next(&mut self) -> Option<Token<'a>> {
+    type Item = SpannedToken<'a>;
+    fn next(&mut self) -> Option<SpannedToken<'a>> {
         self.next_token()
     }
 }
@@ -169,7 +549,6 @@ impl<'a> Iterator for Lexer<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use token::Token::*;
 
 
     // #[test]
@@ -243,7 +622,7 @@ mod tests {
                     };
 
                     let result = add(five, ten);
-                    !-/*5;
+                    !-/ *5;
                     5 < 10 > 5;
 
                     if (5 < 10) {
@@ -339,10 +718,10 @@ Token::SEMICOLON,
         let mut tokens = Vec::new();
 
         loop {
-            let tok = lexer.next_token();
+            let tok = lexer.next_token().map(|t| t.token);
 
             match tok {
-                Some(ILLEGAL) => panic!("Illegal token: {:#?}", tok),
+                Some(ILLEGAL(_)) => panic!("Illegal token: {:#?}", tok),
                 Some(t) => {
                     tokens.push(t);
                 }
@@ -350,10 +729,347 @@ Token::SEMICOLON,
             }
         }
 
-        for (actual, expected) in tokens.into_iter().zip(expected.into_iter()) {
+        for (actual, expected) in tokens.into_iter().zip(expected) {
             if actual != expected {
                 assert_eq!(actual, expected);
             }
         }
     }
+
+    #[test]
+    fn test_spans() {
+        let input = "let x = 10;\n  x";
+        let spans: Vec<(Token, Span)> = Lexer::new(input).map(|t| (t.token, t.span)).collect();
+
+        assert_eq!(spans,
+                   vec![
+                       (LET, Span { start: 0, end: 3, line: 1, column: 1 }),
+                       (IDENT("x"), Span { start: 4, end: 5, line: 1, column: 5 }),
+                       (ASSIGN, Span { start: 6, end: 7, line: 1, column: 7 }),
+                       (INT(10), Span { start: 8, end: 10, line: 1, column: 9 }),
+                       (SEMICOLON, Span { start: 10, end: 11, line: 1, column: 11 }),
+                       (IDENT("x"), Span { start: 14, end: 15, line: 2, column: 3 }),
+                   ]);
+    }
+
+    #[test]
+    fn test_strings() {
+        let input = r#"let s = "hello world"; "a\"b\\c\n\u{1F600}""#;
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+
+        assert_eq!(tokens,
+                   vec![LET,
+                        IDENT("s"),
+                        ASSIGN,
+                        STRING("hello world"),
+                        SEMICOLON,
+                        STRING(r#"a\"b\\c\n\u{1F600}"#)]);
+
+        assert_eq!(unescape(r#"a\"b\\c\n\t\u{1F600}"#),
+                   Ok("a\"b\\c\n\t\u{1F600}".to_owned()));
+        assert_eq!(unescape(""), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_string_errors() {
+        let cases = vec![
+            (r#""abc"#, LexError::UnterminatedString),
+            (r#""abc\"#, LexError::UnterminatedString),
+            (r#""a\qb""#, LexError::InvalidEscape('q')),
+            (r#""\u{110000}""#, LexError::InvalidUnicodeEscape),
+            (r#""\u1234""#, LexError::InvalidUnicodeEscape),
+            (r#""\u{}""#, LexError::InvalidUnicodeEscape),
+        ];
+
+        for (input, expected) in cases {
+            let tok = Lexer::new(input).next().unwrap();
+            assert_eq!(tok.token, ILLEGAL(expected), "{}", input);
+            assert_eq!(tok.span.start, 0);
+        }
+    }
+
+    #[test]
+    fn test_loop_keywords() {
+        let input = "while for loop break continue";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens, vec![WHILE, FOR, LOOP, BREAK, CONTINUE]);
+    }
+
+    #[test]
+    fn test_comparison_and_logical_operators() {
+        let input = "a <= b >= c && d || e < = & |";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![IDENT("a"),
+                        LT_EQ,
+                        IDENT("b"),
+                        GT_EQ,
+                        IDENT("c"),
+                        AND,
+                        IDENT("d"),
+                        OR,
+                        IDENT("e"),
+                        LT,
+                        ASSIGN,
+                        AMPERSAND,
+                        PIPE]);
+    }
+
+    #[test]
+    fn test_arithmetic_and_bitwise_operators() {
+        let input = "a % b ** c * d & e | f ^ ~g << h >> i";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![IDENT("a"),
+                        PERCENT,
+                        IDENT("b"),
+                        POWER,
+                        IDENT("c"),
+                        ASTERISK,
+                        IDENT("d"),
+                        AMPERSAND,
+                        IDENT("e"),
+                        PIPE,
+                        IDENT("f"),
+                        CARET,
+                        TILDE,
+                        IDENT("g"),
+                        SHL,
+                        IDENT("h"),
+                        SHR,
+                        IDENT("i")]);
+    }
+
+    #[test]
+    fn test_assignment_operators() {
+        let input = "a = b += c -= d *= e /= f ** g + = -1";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![IDENT("a"),
+                        ASSIGN,
+                        IDENT("b"),
+                        PLUS_ASSIGN,
+                        IDENT("c"),
+                        MINUS_ASSIGN,
+                        IDENT("d"),
+                        ASTERISK_ASSIGN,
+                        IDENT("e"),
+                        SLASH_ASSIGN,
+                        IDENT("f"),
+                        POWER,
+                        IDENT("g"),
+                        PLUS,
+                        ASSIGN,
+                        MINUS,
+                        INT(1)]);
+    }
+
+    #[test]
+    fn test_ellipsis() {
+        let tokens: Vec<Token> = Lexer::new("[a, ...rest] .. .").map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![LBRACKET,
+                        IDENT("a"),
+                        COMMA,
+                        ELLIPSIS,
+                        IDENT("rest"),
+                        RBRACKET,
+                        ILLEGAL(LexError::UnexpectedChar('.')),
+                        ILLEGAL(LexError::UnexpectedChar('.')),
+                        ILLEGAL(LexError::UnexpectedChar('.'))]);
+    }
+
+    #[test]
+    fn test_brackets() {
+        let tokens: Vec<Token> = Lexer::new("xs[0] = [1]").map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![IDENT("xs"),
+                        LBRACKET,
+                        INT(0),
+                        RBRACKET,
+                        ASSIGN,
+                        LBRACKET,
+                        INT(1),
+                        RBRACKET]);
+    }
+
+    #[test]
+    fn test_hash_tokens() {
+        let tokens: Vec<Token> = Lexer::new(r#"{"a": 1}"#).map(|t| t.token).collect();
+        assert_eq!(tokens, vec![LBRACE, STRING("a"), COLON, INT(1), RBRACE]);
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "let a = 1; // the first
+                     /* a /* nested */ comment */ a / 2 // trailing";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![LET, IDENT("a"), ASSIGN, INT(1), SEMICOLON, IDENT("a"), SLASH, INT(2)]);
+
+        let tokens: Vec<Token> = Lexer::with_comments(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![LET,
+                        IDENT("a"),
+                        ASSIGN,
+                        INT(1),
+                        SEMICOLON,
+                        COMMENT("// the first"),
+                        COMMENT("/* a /* nested */ comment */"),
+                        IDENT("a"),
+                        SLASH,
+                        INT(2),
+                        COMMENT("// trailing")]);
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let tokens: Vec<SpannedToken> = Lexer::new("1 /* a /* b */").collect();
+        assert_eq!(tokens[1].token, ILLEGAL(LexError::UnterminatedComment));
+        assert_eq!(tokens[1].span, Span { start: 2, end: 14, line: 1, column: 3 });
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn test_non_ascii_source() {
+        let input = "let café = \"☕ crème\"; // ünïcödé\nlet 名前 = café;";
+        let tokens: Vec<SpannedToken> = Lexer::new(input).collect();
+
+        let expected = vec![
+            (LET, 0, 3, 1, 1),
+            (IDENT("café"), 4, 9, 1, 5),
+            (ASSIGN, 10, 11, 1, 10),
+            (STRING("☕ crème"), 12, 24, 1, 12),
+            (SEMICOLON, 24, 25, 1, 21),
+            (LET, 41, 44, 2, 1),
+            (IDENT("名前"), 45, 51, 2, 5),
+            (ASSIGN, 52, 53, 2, 8),
+            (IDENT("café"), 54, 59, 2, 10),
+            (SEMICOLON, 59, 60, 2, 14),
+        ];
+        let actual: Vec<(Token, usize, usize, usize, usize)> = tokens.iter()
+            .map(|t| (t.token, t.span.start, t.span.end, t.span.line, t.span.column))
+            .collect();
+        assert_eq!(actual, expected);
+
+        for tok in tokens {
+            assert_eq!(&input[tok.span.start..tok.span.end], tok.token.to_string());
+        }
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let tokens: Vec<Token> = Lexer::new("αβγ1 Ωmega x\u{301} 😀").map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![IDENT("αβγ1"),
+                        IDENT("Ωmega"),
+                        IDENT("x\u{301}"),
+                        ILLEGAL(LexError::UnexpectedChar('😀'))]);
+    }
+
+    #[test]
+    fn test_identifier_grammar() {
+        let input = "x1 _private __x__1 _ 1x letter let lets mut mutable";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![IDENT("x1"),
+                        IDENT("_private"),
+                        IDENT("__x__1"),
+                        IDENT("_"),
+                        INT(1),
+                        IDENT("x"),
+                        IDENT("letter"),
+                        LET,
+                        IDENT("lets"),
+                        MUT,
+                        IDENT("mutable")]);
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let input = "0 42 1_000_000 0xff 0xDead_Beef 0o755 0b1010_1010 0x_1 18446744073709551615";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![INT(0),
+                        INT(42),
+                        INT(1_000_000),
+                        INT(0xff),
+                        INT(0xdead_beef),
+                        INT(0o755),
+                        INT(0b1010_1010),
+                        INT(1),
+                        INT(u64::MAX)]);
+    }
+
+    #[test]
+    fn test_float_literals() {
+        let input = "1.5 1e10 2.5e-3 6.02E+23 1_000.000_1 0.5";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![FLOAT(1.5), FLOAT(1e10), FLOAT(2.5e-3), FLOAT(6.02e23), FLOAT(1000.0001),
+                        FLOAT(0.5)]);
+
+        // A `.` or `e` without digits after it is not part of the number
+        let tokens: Vec<Token> = Lexer::new("1.x 2e 3e+").map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![INT(1),
+                        ILLEGAL(LexError::UnexpectedChar('.')),
+                        IDENT("x"),
+                        INT(2),
+                        IDENT("e"),
+                        INT(3),
+                        IDENT("e"),
+                        PLUS]);
+
+        let tokens: Vec<SpannedToken> = Lexer::new("1e400").collect();
+        assert_eq!(tokens,
+                   vec![SpannedToken {
+                            token: ILLEGAL(LexError::FloatOverflow),
+                            span: Span { start: 0, end: 5, line: 1, column: 1 },
+                        }]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_integer_overflow() {
+        for input in &["99999999999999999999", "0x1_0000_0000_0000_0000"] {
+            let tokens: Vec<SpannedToken> = Lexer::new(input).collect();
+            assert_eq!(tokens,
+                       vec![SpannedToken {
+                                token: ILLEGAL(LexError::IntegerOverflow),
+                                span: Span { start: 0, end: input.len(), line: 1, column: 1 },
+                            }]);
+        }
+        assert_eq!(LexError::IntegerOverflow.to_string(),
+                   "integer literal is larger than 9223372036854775807");
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_big_integer_literals() {
+        let input = "99999999999999999999 0x1_0000_0000_0000_0000";
+        let tokens: Vec<Token> = Lexer::new(input).map(|t| t.token).collect();
+        assert_eq!(tokens,
+                   vec![BIG_INT("99999999999999999999"), BIG_INT("0x1_0000_0000_0000_0000")]);
+        assert_eq!(parse_big_integer("0x1_0000_0000_0000_0000"),
+                   Some(BigInt::from(u64::MAX) + 1));
+        assert_eq!(parse_big_integer("0b1_0"), Some(BigInt::from(2)));
+    }
+
+    #[test]
+    fn test_number_errors() {
+        let cases = vec![
+            ("0b102", LexError::InvalidDigit('2'), 5),
+            ("0o78", LexError::InvalidDigit('8'), 4),
+            ("0x", LexError::MissingDigits, 2),
+            ("0b__", LexError::MissingDigits, 4),
+        ];
+
+        for (input, expected, end) in cases {
+            let tokens: Vec<SpannedToken> = Lexer::new(input).collect();
+            assert_eq!(tokens.len(), 1, "{}", input);
+            assert_eq!(tokens[0].token, ILLEGAL(expected), "{}", input);
+            assert_eq!(tokens[0].span, Span { start: 0, end, line: 1, column: 1 });
+        }
+    }
 }
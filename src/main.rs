@@ -1,13 +1,103 @@
-#![feature(const_fn)]
 #![allow(dead_code)]
 #[macro_use]
 extern crate lazy_static;
+extern crate unicode_xid;
+extern crate typed_arena;
+#[cfg(feature = "bigint")]
+extern crate num_bigint;
+#[cfg(feature = "bigint")]
+extern crate num_traits;
 
 pub mod token;
 pub mod lexer;
 pub mod ast;
 pub mod parser;
+pub mod check;
+pub mod eval;
+pub mod repl;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+use check::Checker;
+use eval::{Environment, Object};
+use lexer::Lexer;
+use parser::Parser;
+use token::Token;
+
+const USAGE: &str = "usage: monkeyrs [script.monkey | -]";
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.len() {
+        0 => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+
+            if let Err(e) = repl::start(stdin.lock(), stdout.lock()) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        1 => process::exit(run_script(&args[0])),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+/// Runs the script at `path` (or stdin for `-`), returning the process exit code.
+fn run_script(path: &str) -> i32 {
+    let mut source = String::new();
+    let read = if path == "-" {
+        io::stdin().read_to_string(&mut source)
+    } else {
+        File::open(path).and_then(|mut f| f.read_to_string(&mut source))
+    };
+
+    if let Err(e) = read {
+        eprintln!("{}: {}", path, e);
+        return 2;
+    }
+
+    for tok in Lexer::new(&source) {
+        if let Token::ILLEGAL(e) = tok.token {
+            eprintln!("{}:{}: lex error: {}", path, tok.span, e);
+            return 1;
+        }
+    }
+
+    let program = match Parser::new(Lexer::new(&source)).parse_program() {
+        Ok(program) => program,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}:{}: parse error: {}", path, e.span, e);
+            }
+            return 1;
+        }
+    };
+
+    if let Err(errors) = Checker::new().check_program(&program) {
+        for e in errors {
+            eprintln!("{}:{}: check error: {}", path, e.span, e);
+        }
+        return 1;
+    }
+
+    let result = Environment::new().eval_program(&program);
+    match result {
+        Ok(Object::Unit) => 0,
+        Ok(value) => {
+            println!("{}", value);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}:{}: runtime error: {}", path, e.span, e);
+            1
+        }
+    }
 }
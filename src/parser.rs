@@ -1,319 +1,934 @@
 // use std::mem::replace;
 
 use ast::*;
-use lexer::Lexer;
-use token::Token;
+use lexer::{self, Lexer};
+use token::{Span, SpannedToken, Token};
+use std::collections::HashSet;
+use std::fmt;
 use std::iter::Peekable;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Parser<'a> {
     token_iter: Peekable<Lexer<'a>>,
-    cur_token: Option<Token<'a>>,
+    cur: Option<SpannedToken<'a>>,
+    // Span of the most recent token, reported for errors at end of input
+    last_span: Span,
+    // Number of loops enclosing the current token within the current function
+    loop_depth: usize,
+    // Number of levels of nesting around the current token, up to MAX_NESTING
+    depth: usize,
+    // Number of `{` before the current token that are not closed before it
+    braces: usize,
 }
 
 #[derive(Debug, Clone, PartialEq ,PartialOrd)]
 // Numbers must be > 0, but have no meaning other than ordering
 pub enum Precedence {
     Lowest = 1,
-    Equals = 2,
-    LessGreater = 3,
-    Sum = 4,
-    Product = 5,
-    Prefix = 6,
-    Call = 7,
+    Assign = 2,
+    LogicalOr = 3,
+    LogicalAnd = 4,
+    Equals = 5,
+    LessGreater = 6,
+    BitOr = 7,
+    BitXor = 8,
+    BitAnd = 9,
+    Shift = 10,
+    Sum = 11,
+    Product = 12,
+    Prefix = 13,
+    // Above Prefix, so that `-2 ** 2` is `-(2 ** 2)`
+    Power = 14,
+    Call = 15,
+    Index = 16,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind<'a> {
+    // A token that the construct being parsed does not allow there
+    Unexpected {
+        // The construct being parsed, e.g. "let statement"
+        context: &'static str,
+        expected: &'static str,
+        // None if the input ended early
+        found: Option<Token<'a>>,
+    },
+    // Statements and expressions nested more than MAX_NESTING deep
+    NestedTooDeeply,
+    // A name that a pattern or parameter list binds a second time
+    DuplicateName {
+        context: &'static str,
+        name: &'a str,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError<'a> {
+    pub kind: ParseErrorKind<'a>,
+    pub span: Span,
+}
+
+impl<'a> ParseError<'a> {
+    fn new(context: &'static str,
+           expected: &'static str,
+           found: Option<Token<'a>>,
+           span: Span)
+           -> ParseError<'a> {
+        ParseError {
+            kind: ParseErrorKind::Unexpected {
+                context,
+                expected,
+                found,
+            },
+            span,
+        }
+    }
+}
+
+impl<'a> fmt::Display for ParseError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::Unexpected { context, expected, found } => {
+                write!(f, "expected {} in {}, found ", expected, context)?;
+                match found {
+                    Some(Token::ILLEGAL(e)) => write!(f, "{}", e),
+                    Some(tok) => write!(f, "{:?}", tok),
+                    None => write!(f, "end of input"),
+                }
+            }
+            ParseErrorKind::NestedTooDeeply => write!(f, "expression nested too deeply"),
+            ParseErrorKind::DuplicateName { context, name } => {
+                write!(f, "{} is bound more than once in {}", name, context)
+            }
+        }
+    }
+}
+
+pub type ParseResult<'a> = Result<Node<'a>, ParseError<'a>>;
+
+// The most levels of statements, expressions and patterns that may be nested
+// in each other. Parsing recurses once for each level, so deeper programs
+// could overflow the stack. Chains of left-associative operators are parsed
+// in a loop, and don't count.
+const MAX_NESTING: usize = 256;
+
+// Expected description when a keyword is used where a binding name belongs
+const RESERVED_NAME: &str = "an identifier that is not a reserved keyword";
+const INTEGER_IN_RANGE: &str = "an integer no larger than 9223372036854775807";
+// The magnitude of `i64::MIN`, which is only a valid literal when negated
+const MIN_INTEGER_MAGNITUDE: u64 = 1 << 63;
+
 impl<'a> Parser<'a> {
     pub fn new(lexer: Lexer<'a>) -> Parser<'a> {
         let mut p = lexer.peekable();
-        let cur_token = p.next();
+        let cur = p.next();
         Parser {
             token_iter: p,
-            cur_token: cur_token,
+            cur,
+            last_span: cur.map(|t| t.span).unwrap_or_default(),
+            loop_depth: 0,
+            depth: 0,
+            braces: 0,
         }
     }
 
     pub fn get_cur_token(&self) -> Option<Token<'a>> {
-        self.cur_token
+        self.cur.map(|t| t.token)
+    }
+
+    pub fn cur_span(&self) -> Span {
+        self.cur.map(|t| t.span).unwrap_or(self.last_span)
     }
 
     pub fn peek_token(&mut self) -> Option<Token<'a>> {
-        self.token_iter.peek().cloned()
+        self.token_iter.peek().map(|t| t.token)
+    }
+
+    fn peek_span(&mut self) -> Span {
+        let last_span = self.cur_span();
+        self.token_iter.peek().map(|t| t.span).unwrap_or(last_span)
     }
 
     pub fn next_token(&mut self) -> Option<Token<'a>> {
-        self.cur_token = self.peek_token();
-        self.token_iter.next()
+        match self.get_cur_token() {
+            Some(Token::LBRACE) => self.braces += 1,
+            Some(Token::RBRACE) => self.braces = self.braces.saturating_sub(1),
+            _ => {}
+        }
+        self.last_span = self.cur_span();
+        self.cur = self.token_iter.next();
+        self.get_cur_token()
     }
 
-    pub fn parse_program(&mut self) -> Program {
-        let mut program = Program::new();
+    fn cur_error(&self, context: &'static str, expected: &'static str) -> ParseError<'a> {
+        ParseError::new(context, expected, self.get_cur_token(), self.cur_span())
+    }
 
-        loop {
-            if let None = self.get_cur_token() {
-                break;
-            }
-            let stmt = self.parse_statement();
-            if let Some(st) = stmt {
-                program.statements.push(st);
+    fn peek_error(&mut self, context: &'static str, expected: &'static str) -> ParseError<'a> {
+        let found = self.peek_token();
+        let span = self.peek_span();
+        ParseError::new(context, expected, found, span)
+    }
+
+    // Advances if the next token is `expected`, without consuming anything otherwise
+    fn expect_peek(&mut self,
+                   expected: Token<'a>,
+                   context: &'static str,
+                   description: &'static str)
+                   -> Result<(), ParseError<'a>> {
+        if self.peek_token() == Some(expected) {
+            self.next_token();
+            Ok(())
+        } else {
+            Err(self.peek_error(context, description))
+        }
+    }
+
+    // Runs `parse` one level of nesting deeper than the current one
+    fn nested<T, F>(&mut self, parse: F) -> Result<T, ParseError<'a>>
+        where F: FnOnce(&mut Parser<'a>) -> Result<T, ParseError<'a>>
+    {
+        let depth = self.depth;
+        let result = self.descend().and_then(|()| parse(self));
+        self.depth = depth;
+        result
+    }
+
+    fn descend(&mut self) -> Result<(), ParseError<'a>> {
+        if self.depth == MAX_NESTING {
+            return Err(ParseError {
+                kind: ParseErrorKind::NestedTooDeeply,
+                span: self.cur_span(),
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Skips the rest of a broken statement so parsing can resume after it,
+    // including any blocks the error was inside of. The statement ends at a
+    // `;` outside of every block, or at the `}` of its last block unless an
+    // `else` or an operator goes on after it.
+    fn synchronize(&mut self) {
+        while let Some(tok) = self.get_cur_token() {
+            match tok {
+                Token::SEMICOLON if self.braces == 0 => break,
+                Token::RBRACE if self.braces <= 1 && self.peek_token() != Some(Token::ELSE) &&
+                                 self.peek_precedence() == Precedence::Lowest => break,
+                _ => {}
             }
+            self.next_token();
+        }
+    }
 
+    // Every parse_* method expects cur_token to be the first token of the
+    // construct it parses, and leaves cur_token on the last token of it.
+    pub fn parse_program(&mut self) -> Result<Program<'a>, Vec<ParseError<'a>>> {
+        let mut program = Program::new();
+        let mut errors = Vec::new();
+
+        while let Some(tok) = self.get_cur_token() {
+            if tok != Token::SEMICOLON {
+                match self.parse_statement() {
+                    Ok(st) => program.statements.push(st),
+                    Err(e) => {
+                        errors.push(e);
+                        self.synchronize();
+                    }
+                }
+            }
 
             self.next_token();
         }
 
-        program
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn parse_statement(&mut self) -> ParseResult<'a> {
+        self.nested(Parser::parse_statement_kind)
     }
 
-    pub fn parse_statement(&mut self) -> Option<Node<'a>> {
+    fn parse_statement_kind(&mut self) -> ParseResult<'a> {
         match self.get_cur_token() {
-            Some(Token::LET) => Some(self.parse_let_statement()),
-            Some(Token::RETURN) => Some(self.parse_return_statement()),
-            Some(Token::LBRACE) => Some(self.parse_block_statement()),
+            Some(Token::LET) => self.parse_let_statement(),
+            // `fn(` starts an anonymous function expression instead
+            Some(Token::FUNCTION) if self.peek_token() != Some(Token::LPAREN) => {
+                self.parse_function_declaration()
+            }
+            Some(Token::RETURN) => self.parse_return_statement(),
+            Some(Token::LBRACE) if !self.starts_hash_literal() => self.parse_block_statement(),
+            Some(Token::WHILE) => self.parse_while_statement(),
+            Some(Token::FOR) => self.parse_for_statement(),
+            Some(Token::LOOP) => self.parse_loop_statement(),
+            Some(Token::BREAK) |
+            Some(Token::CONTINUE) => self.parse_loop_control_statement(),
             _ => self.parse_expression_statement(),
         }
     }
 
-    pub fn parse_expression_statement(&mut self) -> Option<Node<'a>> {
-        let expr = self.parse_expression(Precedence::Lowest);
+    // Whether the `{` at `cur` opens a hash literal rather than a block: a
+    // hash has a `:` after its first key, before any `;` or the closing `}`
+    fn starts_hash_literal(&self) -> bool {
+        let mut depth = 0;
+        for tok in self.token_iter.clone() {
+            match tok.token {
+                Token::COLON if depth == 0 => return true,
+                Token::SEMICOLON |
+                Token::RPAREN |
+                Token::RBRACKET |
+                Token::RBRACE if depth == 0 => return false,
+                Token::LPAREN | Token::LBRACKET | Token::LBRACE => depth += 1,
+                Token::RPAREN | Token::RBRACKET | Token::RBRACE => depth -= 1,
+                _ => {}
+            }
+        }
+        false
+    }
 
-        if let Some(&Token::SEMICOLON) = self.token_iter.peek() {
-            self.next_token();
+    pub fn parse_while_statement(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("while statement without a token");
+        let start = self.cur_span();
+
+        self.expect_peek(Token::LPAREN, "while statement", "LPAREN")?;
+        self.next_token();
+        let condition = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(Token::RPAREN, "while statement", "RPAREN")?;
+
+        self.expect_peek(Token::LBRACE, "while statement", "LBRACE")?;
+        let body = self.parse_loop_body()?;
+
+        Ok(Node::WhileStatement {
+            token,
+            span: start.to(self.cur_span()),
+            condition: Box::new(condition),
+            body: Box::new(body),
+        })
+    }
+
+    // for (init; condition; update) { body }, where each clause may be empty
+    pub fn parse_for_statement(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("for statement without a token");
+        let start = self.cur_span();
+
+        self.expect_peek(Token::LPAREN, "for statement", "LPAREN")?;
+        self.next_token();
+
+        let init = match self.get_cur_token() {
+            Some(Token::SEMICOLON) => None,
+            Some(Token::LET) => Some(self.parse_let_statement()?),
+            _ => Some(self.parse_expression_statement()?),
+        };
+        // Both statement forms above consume their trailing semicolon
+        if self.get_cur_token() != Some(Token::SEMICOLON) {
+            return Err(self.peek_error("for statement", "SEMICOLON"));
         }
 
-        expr
+        let condition = match self.peek_token() {
+            Some(Token::SEMICOLON) => None,
+            _ => {
+                self.next_token();
+                Some(self.parse_expression(Precedence::Lowest)?)
+            }
+        };
+        self.expect_peek(Token::SEMICOLON, "for statement", "SEMICOLON")?;
+
+        let update = match self.peek_token() {
+            Some(Token::RPAREN) => None,
+            _ => {
+                self.next_token();
+                Some(self.parse_expression(Precedence::Lowest)?)
+            }
+        };
+        self.expect_peek(Token::RPAREN, "for statement", "RPAREN")?;
+
+        self.expect_peek(Token::LBRACE, "for statement", "LBRACE")?;
+        let body = self.parse_loop_body()?;
+
+        Ok(Node::ForStatement {
+            token,
+            span: start.to(self.cur_span()),
+            init: init.map(Box::new),
+            condition: condition.map(Box::new),
+            update: update.map(Box::new),
+            body: Box::new(body),
+        })
+    }
+
+    pub fn parse_loop_statement(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("loop statement without a token");
+        let start = self.cur_span();
+
+        self.expect_peek(Token::LBRACE, "loop statement", "LBRACE")?;
+        let body = self.parse_loop_body()?;
+
+        Ok(Node::LoopStatement {
+            token,
+            span: start.to(self.cur_span()),
+            body: Box::new(body),
+        })
+    }
+
+    fn parse_loop_body(&mut self) -> ParseResult<'a> {
+        self.loop_depth += 1;
+        let body = self.parse_block_statement();
+        self.loop_depth -= 1;
+        body
     }
 
-    pub fn parse_function_literal(&mut self) -> Option<Node<'a>> {
-        let tok = self.next_token();
+    pub fn parse_loop_control_statement(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("loop control statement without a token");
+        let span = self.cur_span();
 
-        if let Some(Token::LPAREN) = self.peek_token() {
+        if self.loop_depth == 0 {
+            return Err(self.cur_error("loop control statement", "an enclosing loop"));
+        }
+
+        if let Some(Token::SEMICOLON) = self.peek_token() {
             self.next_token();
+        }
+
+        if token == Token::BREAK {
+            Ok(Node::BreakStatement { token, span })
         } else {
-            return None;
+            Ok(Node::ContinueStatement { token, span })
         }
+    }
 
-        let params = self.parse_function_parameters();
+    pub fn parse_expression_statement(&mut self) -> ParseResult<'a> {
+        let expr = self.parse_expression(Precedence::Lowest)?;
 
-        if let Some(Token::LBRACE) = self.peek_token() {
+        if let Some(Token::SEMICOLON) = self.peek_token() {
             self.next_token();
-        } else {
-            panic!("{:#?}", self.cur_token);
-            return None;
         }
 
-        let fn_body = self.parse_block_statement();
+        Ok(expr)
+    }
+
+    pub fn parse_function_literal(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("function literal without a token");
+        let start = self.cur_span();
+
+        let (parameters, body) = self.parse_function_parts("function literal")?;
 
-        Some(Node::FunctionLiteral {
-            token: tok.unwrap(),
-            parameters: params,
-            body: Box::new(fn_body),
+        Ok(Node::FunctionLiteral {
+            token,
+            span: start.to(self.cur_span()),
+            parameters: Rc::new(parameters),
+            body: Rc::new(body),
         })
     }
 
-    pub fn parse_function_parameters(&mut self) -> Vec<Node<'a>> {
-        let mut identifiers = Vec::new();
+    pub fn parse_function_declaration(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("function declaration without a token");
+        let start = self.cur_span();
 
-        if Some(Token::RPAREN) == self.peek_token() {
+        let name = match self.peek_token() {
+            Some(tok @ Token::IDENT(name)) => {
+                self.next_token();
+                Node::Identifier {
+                    token: tok,
+                    span: self.cur_span(),
+                    value: name,
+                }
+            }
+            Some(tok) if tok.is_keyword() => {
+                return Err(self.peek_error("function declaration", RESERVED_NAME));
+            }
+            _ => return Err(self.peek_error("function declaration", "IDENT or LPAREN")),
+        };
+
+        let (parameters, body) = self.parse_function_parts("function declaration")?;
+        let span = start.to(self.cur_span());
+
+        if let Some(Token::SEMICOLON) = self.peek_token() {
             self.next_token();
-            return identifiers;
         }
 
-        self.next_token();
+        Ok(Node::FunctionDeclaration {
+            token,
+            span,
+            name: Box::new(name),
+            parameters: Rc::new(parameters),
+            body: Rc::new(body),
+        })
+    }
 
-        match self.cur_token {
-            Some(Token::IDENT(name)) => {
-                identifiers.push(Node::Identifier {
-                    token: self.cur_token.expect("cur_token was none"),
-                    value: name,
-                });
-            },
-            _   => panic!("Expected identifier for function parameter!")
+    // Parses the parameter list and body that follow `fn` or a function's name
+    fn parse_function_parts(&mut self,
+                            context: &'static str)
+                            -> Result<(Vec<Node<'a>>, Node<'a>), ParseError<'a>> {
+        self.expect_peek(Token::LPAREN, context, "LPAREN")?;
+
+        let parameters = self.parse_function_parameters()?;
+
+        self.expect_peek(Token::LBRACE, context, "LBRACE")?;
+
+        // break and continue cannot reach a loop outside of the function
+        let loop_depth = self.loop_depth;
+        self.loop_depth = 0;
+        let body = self.parse_block_statement();
+        self.loop_depth = loop_depth;
+
+        Ok((parameters, body?))
+    }
+
+    pub fn parse_function_parameters(&mut self) -> Result<Vec<Node<'a>>, ParseError<'a>> {
+        let mut identifiers = Vec::new();
+
+        if Some(Token::RPAREN) == self.peek_token() {
+            self.next_token();
+            return Ok(identifiers);
         }
 
         loop {
-            if let Some(Token::COMMA) = self.peek_token() {
-                self.next_token();
-                self.next_token();
+            self.next_token();
 
-                match self.cur_token {
-                    Some(Token::IDENT(name)) => {
-                        identifiers.push(Node::Identifier {
-                            token: self.cur_token.expect("cur_token was none"),
-                            value: name,
-                        });
-                    },
-                    _    => panic!("Expected identifier for function parameter!")
+            match self.get_cur_token() {
+                Some(tok @ Token::IDENT(name)) => {
+                    identifiers.push(Node::Identifier {
+                        token: tok,
+                        span: self.cur_span(),
+                        value: name,
+                    });
+                },
+                Some(tok) if tok.is_keyword() => {
+                    return Err(self.cur_error("function parameters", RESERVED_NAME));
                 }
+                _ => return Err(self.cur_error("function parameters", "IDENT")),
+            }
+
+            if let Some(Token::COMMA) = self.peek_token() {
+                self.next_token();
             } else {
                 break;
             }
         }
 
-        match self.peek_token() {
-         Some(Token::RPAREN) => {
-                self.next_token();
-                identifiers
-         },
-            _ => panic!("Unexpected token after function params")
-        }
+        self.expect_peek(Token::RPAREN, "function parameters", "COMMA or RPAREN")?;
+        let names: Vec<&Node<'a>> = identifiers.iter().collect();
+        Parser::check_unique_names("function parameters", &names)?;
+        Ok(identifiers)
     }
 
-    pub fn parse_expression(&mut self, precedence: Precedence) -> Option<Node<'a>> {
-        let tok = self.get_cur_token().expect("parse_expression get_cur_token");
-        let mut left_expr = match self.prefix_parse(tok) {
-            Some(le) => le,
-            None => return None,
-        };
-
-        loop {
-            let peek_tok = self.peek_token();
-            let peek_prec = self.peek_precedence();
+    pub fn parse_expression(&mut self, precedence: Precedence) -> ParseResult<'a> {
+        self.nested(|p| p.parse_operators(precedence))
+    }
 
-            if Some(Token::SEMICOLON) != peek_tok && precedence >= peek_prec {
-                break;
-            }
+    fn parse_operators(&mut self, precedence: Precedence) -> ParseResult<'a> {
+        let mut left_expr = self.prefix_parse()?;
 
-            self.next_token();
+        while Some(Token::SEMICOLON) != self.peek_token() && precedence < self.peek_precedence() {
+            let peek_tok = self.next_token().expect("peek_tok");
 
-            left_expr = match self.infix_parse(peek_tok.expect("peek_tok"), left_expr.clone()) {
-                Some(le) => {
-                    self.next_token();
-                    le
-                }
-                None => return Some(left_expr),
-            };
+            left_expr = self.infix_parse(peek_tok, left_expr)?;
         }
 
-        Some(left_expr)
+        Ok(left_expr)
     }
 
     fn peek_precedence(&mut self) -> Precedence {
-        self.token_iter.peek().map(Token::get_precedence).unwrap_or(Precedence::Lowest)
+        self.token_iter.peek().map(|t| t.token.get_precedence()).unwrap_or(Precedence::Lowest)
     }
 
     fn cur_precedence(&self) -> Precedence {
-        self.cur_token.unwrap().get_precedence()
+        self.get_cur_token().map(|t| t.get_precedence()).unwrap_or(Precedence::Lowest)
     }
 
-    pub fn parse_integer_literal(&mut self) -> Node<'a> {
-        match self.cur_token {
-            Some(Token::INT(i)) => {
-                Node::IntegerLiteral {
-                    token: self.cur_token.unwrap(),
-                    value: i,
-                }
+    pub fn parse_integer_literal(&mut self) -> ParseResult<'a> {
+        match self.get_cur_token() {
+            #[cfg(feature = "bigint")]
+            Some(tok @ Token::INT(i)) if i > i64::MAX as u64 => {
+                Ok(Node::BigIntegerLiteral {
+                    token: tok,
+                    span: self.cur_span(),
+                    value: i.into(),
+                })
+            }
+            #[cfg(feature = "bigint")]
+            Some(tok @ Token::BIG_INT(raw)) => {
+                // The lexer only produces BIG_INT tokens with valid digits
+                let value = lexer::parse_big_integer(raw)
+                    .ok_or_else(|| self.cur_error("integer literal", "INT"))?;
+                Ok(Node::BigIntegerLiteral {
+                    token: tok,
+                    span: self.cur_span(),
+                    value,
+                })
+            }
+            Some(Token::INT(i)) if i > i64::MAX as u64 => {
+                Err(self.cur_error("integer literal", INTEGER_IN_RANGE))
+            }
+            Some(tok @ Token::INT(i)) => {
+                Ok(Node::IntegerLiteral {
+                    token: tok,
+                    span: self.cur_span(),
+                    value: i as i64,
+                })
             }
-            _ => panic!(),
+            _ => Err(self.cur_error("integer literal", "INT")),
         }
     }
 
-    pub fn parse_return_statement(&mut self) -> Node<'a> {
-        let init_token = self.get_cur_token().take().unwrap();
+    pub fn parse_float_literal(&mut self) -> ParseResult<'a> {
+        match self.get_cur_token() {
+            Some(tok @ Token::FLOAT(x)) => {
+                Ok(Node::FloatLiteral {
+                    token: tok,
+                    span: self.cur_span(),
+                    value: x,
+                })
+            }
+            _ => Err(self.cur_error("float literal", "FLOAT")),
+        }
+    }
 
-        self.next_token();
+    pub fn parse_string_literal(&mut self) -> ParseResult<'a> {
+        match self.get_cur_token() {
+            Some(tok @ Token::STRING(raw)) => {
+                // The lexer only produces STRING tokens with valid escapes
+                let value = lexer::unescape(raw)
+                    .map_err(|_| self.cur_error("string literal", "STRING"))?;
+                Ok(Node::StringLiteral {
+                    token: tok,
+                    span: self.cur_span(),
+                    value,
+                })
+            }
+            _ => Err(self.cur_error("string literal", "STRING")),
+        }
+    }
 
-        let value = self.parse_expression(Precedence::Lowest);
+    pub fn parse_return_statement(&mut self) -> ParseResult<'a> {
+        let init_token = self.get_cur_token().expect("return statement without a token");
+        let start = self.cur_span();
 
+        let value = match self.peek_token() {
+            None | Some(Token::SEMICOLON) | Some(Token::RBRACE) => None,
+            _ => {
+                self.next_token();
+                Some(self.parse_expression(Precedence::Lowest)?)
+            }
+        };
+        let span = start.to(self.cur_span());
 
-        Node::ReturnStatement {
+        if let Some(Token::SEMICOLON) = self.peek_token() {
+            self.next_token();
+        }
+
+        Ok(Node::ReturnStatement {
             token: init_token,
+            span,
             value: value.map(Box::new),
-        }
+        })
     }
 
-    pub fn parse_let_statement(&mut self) -> Node<'a> {
-        let init_token = self.cur_token.clone();
+    pub fn parse_let_statement(&mut self) -> ParseResult<'a> {
+        let init_token = self.get_cur_token().expect("let statement without a token");
+        let start = self.cur_span();
 
-        let ident = if let Some(Token::IDENT(name)) = self.peek_token() {
+        let mutable = self.peek_token() == Some(Token::MUT);
+        if mutable {
             self.next_token();
-            Node::Identifier {
-                token: self.cur_token.expect("Failed to get cur_token in parse_let_statement"),
-                value: name,
-            }
-        } else {
-            panic!("Expected identifier");
-        };
+        }
 
-        assert!(Some(Token::ASSIGN) == self.peek_token(),
-                format!("{:#?}", self.peek_token()));
-        self.next_token();
+        let pattern = self.parse_pattern("let statement")?;
+        Parser::check_unique_names("let statement", &pattern.names())?;
+
+        self.expect_peek(Token::ASSIGN, "let statement", "ASSIGN")?;
         self.next_token();
 
-        let value = self.parse_expression(Precedence::Lowest);
+        let value = self.parse_expression(Precedence::Lowest)?;
+        let span = start.to(self.cur_span());
 
         if let Some(Token::SEMICOLON) = self.peek_token() {
             self.next_token();
-            self.next_token();
         };
 
-        Node::LetStatement {
-            token: init_token.expect("init token is None"),
-            name: Box::new(ident),
-            value: Box::new(value.expect("let statement with empty values")),
-        }
+        Ok(Node::LetStatement {
+            token: init_token,
+            span,
+            mutable,
+            pattern,
+            value: Box::new(value),
+        })
 
     }
 
+    // Parses the pattern that starts at the peek token
+    fn parse_pattern(&mut self, context: &'static str) -> Result<Pattern<'a>, ParseError<'a>> {
+        match self.peek_token() {
+            Some(Token::LBRACKET) => {
+                self.next_token();
+                self.nested(Parser::parse_array_pattern)
+            }
+            Some(Token::LBRACE) => {
+                self.next_token();
+                self.parse_hash_pattern()
+            }
+            _ => {
+                let name = self.parse_binding_name(context, "IDENT, LBRACKET or LBRACE")?;
+                Ok(Pattern::Identifier(Box::new(name)))
+            }
+        }
+    }
 
-    fn prefix_parse(&mut self, tok: Token<'a>) -> Option<Node<'a>> {
-        match tok {
-            Token::IDENT(value) => {
-                Some(Node::Identifier {
+    // Parses the peek token as a name to bind
+    fn parse_binding_name(&mut self,
+                          context: &'static str,
+                          expected: &'static str)
+                          -> ParseResult<'a> {
+        match self.peek_token() {
+            Some(tok @ Token::IDENT(name)) => {
+                self.next_token();
+                Ok(Node::Identifier {
                     token: tok,
-                    value: value,
+                    span: self.cur_span(),
+                    value: name,
                 })
             }
-            Token::INT(i) => {
-                Some(Node::IntegerLiteral {
+            Some(tok) if tok.is_keyword() => Err(self.peek_error(context, RESERVED_NAME)),
+            _ => Err(self.peek_error(context, expected)),
+        }
+    }
+
+    // Fails at the first of the Identifier nodes in `names` that repeats an
+    // earlier one
+    fn check_unique_names(context: &'static str,
+                          names: &[&Node<'a>])
+                          -> Result<(), ParseError<'a>> {
+        let mut seen = HashSet::new();
+        for name in names {
+            if let Node::Identifier { value, span, .. } = **name {
+                if !seen.insert(value) {
+                    return Err(ParseError {
+                        kind: ParseErrorKind::DuplicateName { context, name: value },
+                        span,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `[a, [b, c], ...rest]`, where the rest binding must come last
+    fn parse_array_pattern(&mut self) -> Result<Pattern<'a>, ParseError<'a>> {
+        let token = self.get_cur_token().expect("array pattern without a token");
+        let start = self.cur_span();
+        let mut elements = Vec::new();
+        let mut rest = None;
+
+        if self.peek_token() == Some(Token::RBRACKET) {
+            self.next_token();
+        } else {
+            loop {
+                if self.peek_token() == Some(Token::ELLIPSIS) {
+                    self.next_token();
+                    rest = Some(Box::new(self.parse_binding_name("array pattern", "IDENT")?));
+                    self.expect_peek(Token::RBRACKET, "array pattern", "RBRACKET")?;
+                    break;
+                }
+
+                elements.push(self.parse_pattern("array pattern")?);
+                if self.peek_token() == Some(Token::COMMA) {
+                    self.next_token();
+                } else {
+                    self.expect_peek(Token::RBRACKET, "array pattern", "COMMA or RBRACKET")?;
+                    break;
+                }
+            }
+        }
+
+        Ok(Pattern::Array {
+            token,
+            span: start.to(self.cur_span()),
+            elements,
+            rest,
+        })
+    }
+
+    // `{name, age}`
+    fn parse_hash_pattern(&mut self) -> Result<Pattern<'a>, ParseError<'a>> {
+        let token = self.get_cur_token().expect("hash pattern without a token");
+        let start = self.cur_span();
+        let mut keys = Vec::new();
+
+        if self.peek_token() == Some(Token::RBRACE) {
+            self.next_token();
+        } else {
+            loop {
+                keys.push(self.parse_binding_name("hash pattern", "IDENT")?);
+                if self.peek_token() == Some(Token::COMMA) {
+                    self.next_token();
+                } else {
+                    self.expect_peek(Token::RBRACE, "hash pattern", "COMMA or RBRACE")?;
+                    break;
+                }
+            }
+        }
+
+        Ok(Pattern::Hash {
+            token,
+            span: start.to(self.cur_span()),
+            keys,
+        })
+    }
+
+
+    fn prefix_parse(&mut self) -> ParseResult<'a> {
+        let span = self.cur_span();
+        match self.get_cur_token() {
+            Some(tok @ Token::IDENT(value)) => {
+                Ok(Node::Identifier {
                     token: tok,
-                    value: i,
+                    span,
+                    value,
                 })
             }
-            Token::TRUE => {
-                Some(Node::Boolean {
+            Some(Token::INT(_)) => self.parse_integer_literal(),
+            #[cfg(feature = "bigint")]
+            Some(Token::BIG_INT(_)) => self.parse_integer_literal(),
+            Some(Token::FLOAT(_)) => self.parse_float_literal(),
+            Some(Token::STRING(_)) => self.parse_string_literal(),
+            Some(tok @ Token::TRUE) => {
+                Ok(Node::Boolean {
                     token: tok,
+                    span,
                     value: true,
                 })
             }
-            Token::FALSE => {
-                Some(Node::Boolean {
+            Some(tok @ Token::FALSE) => {
+                Ok(Node::Boolean {
                     token: tok,
+                    span,
                     value: false,
                 })
             }
-            Token::MINUS => Some(self.parse_prefix_expression(tok)),
-            Token::BANG => Some(self.parse_prefix_expression(tok)),
-            Token::FUNCTION => self.parse_function_literal(),
-            // Token::PLUS => Some(self.parse_infix_expression(tok, expr: Node<'a>)),
-            _ => None,
+            Some(tok @ Token::MINUS) => self.parse_prefix_expression(tok),
+            Some(tok @ Token::BANG) => self.parse_prefix_expression(tok),
+            Some(tok @ Token::TILDE) => self.parse_prefix_expression(tok),
+            Some(Token::FUNCTION) => self.parse_function_literal(),
+            Some(Token::IF) => self.parse_if_expression(),
+            Some(Token::LPAREN) => self.parse_grouped_expression(),
+            Some(Token::LBRACKET) => self.parse_array_literal(),
+            Some(Token::LBRACE) => self.parse_hash_literal(),
+            _ => Err(self.cur_error("expression", "an expression")),
         }
     }
 
-    fn parse_prefix_expression(&mut self, tok: Token<'a>) -> Node<'a> {
+    // Parentheses only affect how the inner expression is grouped, so no
+    // node is produced for them.
+    pub fn parse_grouped_expression(&mut self) -> ParseResult<'a> {
+        self.next_token();
+        let expr = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(Token::RPAREN, "grouped expression", "RPAREN")?;
+        Ok(expr)
+    }
+
+    pub fn parse_if_expression(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("if expression without a token");
+        let start = self.cur_span();
+
+        self.expect_peek(Token::LPAREN, "if expression", "LPAREN")?;
         self.next_token();
-        let right = self.parse_expression(Precedence::Prefix);
-        Node::PrefixExpression {
-            token: tok.clone(),
-            operator: Parser::operator_from_tok(tok),
-            right: right.map(Box::new),
+        let condition = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(Token::RPAREN, "if expression", "RPAREN")?;
+
+        self.expect_peek(Token::LBRACE, "if expression", "LBRACE")?;
+        let consequence = self.parse_block_statement()?;
+
+        let alternative = if let Some(Token::ELSE) = self.peek_token() {
+            self.next_token();
+            match self.peek_token() {
+                Some(Token::IF) => {
+                    self.next_token();
+                    Some(self.parse_if_expression()?)
+                }
+                _ => {
+                    self.expect_peek(Token::LBRACE, "else branch", "IF or LBRACE")?;
+                    Some(self.parse_block_statement()?)
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Node::IfExpression {
+            token,
+            span: start.to(self.cur_span()),
+            condition: Box::new(condition),
+            consequence: Box::new(consequence),
+            alternative: alternative.map(Box::new),
+        })
+    }
+
+    fn parse_prefix_expression(&mut self, tok: Token<'a>) -> ParseResult<'a> {
+        let start = self.cur_span();
+        let operator = self.operator_from_tok(tok)?;
+
+        // `i64::MIN` has no positive counterpart to negate, so its literal is
+        // folded into a single negative integer. Big integers can represent
+        // the positive literal and need no special case.
+        #[cfg(not(feature = "bigint"))]
+        {
+            let peek = self.peek_token();
+            if let (Token::MINUS, Some(lit @ Token::INT(MIN_INTEGER_MAGNITUDE))) = (tok, peek) {
+                self.next_token();
+                // In `-9223372036854775808 ** 2` the literal is the base of `**`,
+                // which binds tighter than `-`, so it is not negated after all
+                if self.peek_token() == Some(Token::POWER) {
+                    return Err(self.cur_error("integer literal", INTEGER_IN_RANGE));
+                }
+                return Ok(Node::IntegerLiteral {
+                    token: lit,
+                    span: start.to(self.cur_span()),
+                    value: i64::MIN,
+                });
+            }
         }
+
+        self.next_token();
+        let right = self.parse_expression(Precedence::Prefix)?;
+        Ok(Node::PrefixExpression {
+            token: tok,
+            span: start.to(self.cur_span()),
+            operator,
+            right: Some(Box::new(right)),
+        })
     }
 
-    fn operator_from_tok(tok: Token<'a>) -> &'static str {
+    fn operator_from_tok(&self, tok: Token<'a>) -> Result<&'static str, ParseError<'a>> {
         match tok {
-            Token::PLUS => "PLUS",
-            Token::MINUS => "MINUS",
-            Token::GT => "GT",
-            Token::LT => "LT",
-            Token::BANG => "BANG",
-            Token::ASTERISK => "ASTERISK",
-            Token::SLASH => "SLASH",
-            _ => panic!("Unexpected operator"),
+            Token::PLUS => Ok("PLUS"),
+            Token::MINUS => Ok("MINUS"),
+            Token::GT => Ok("GT"),
+            Token::LT => Ok("LT"),
+            Token::GT_EQ => Ok("GT_EQ"),
+            Token::LT_EQ => Ok("LT_EQ"),
+            Token::AND => Ok("AND"),
+            Token::OR => Ok("OR"),
+            Token::BANG => Ok("BANG"),
+            Token::ASTERISK => Ok("ASTERISK"),
+            Token::SLASH => Ok("SLASH"),
+            Token::PERCENT => Ok("PERCENT"),
+            Token::POWER => Ok("POWER"),
+            Token::AMPERSAND => Ok("AMPERSAND"),
+            Token::PIPE => Ok("PIPE"),
+            Token::CARET => Ok("CARET"),
+            Token::TILDE => Ok("TILDE"),
+            Token::SHL => Ok("SHL"),
+            Token::SHR => Ok("SHR"),
+            Token::EQ => Ok("EQ"),
+            Token::NOT_EQ => Ok("NOT_EQ"),
+            found => Err(ParseError::new("operator", "an operator", Some(found), self.cur_span())),
         }
     }
 
-    fn infix_parse(&mut self, tok: Token<'a>, expr: Node<'a>) -> Option<Node<'a>> {
+    fn infix_parse(&mut self, tok: Token<'a>, expr: Node<'a>) -> ParseResult<'a> {
         match tok {
             tok @ Token::PLUS |
             tok @ Token::MINUS |
@@ -322,85 +937,205 @@ impl<'a> Parser<'a> {
             tok @ Token::EQ |
             tok @ Token::NOT_EQ |
             tok @ Token::LT |
-            tok @ Token::GT => Some(self.parse_infix_expression(tok, expr)),
-            Token::LPAREN => Some(self.parse_call_expression(expr)),
-            _ => None,
+            tok @ Token::GT |
+            tok @ Token::LT_EQ |
+            tok @ Token::GT_EQ |
+            tok @ Token::AND |
+            tok @ Token::OR |
+            tok @ Token::PERCENT |
+            tok @ Token::POWER |
+            tok @ Token::AMPERSAND |
+            tok @ Token::PIPE |
+            tok @ Token::CARET |
+            tok @ Token::SHL |
+            tok @ Token::SHR => self.parse_infix_expression(tok, expr),
+            tok @ Token::ASSIGN |
+            tok @ Token::PLUS_ASSIGN |
+            tok @ Token::MINUS_ASSIGN |
+            tok @ Token::ASTERISK_ASSIGN |
+            tok @ Token::SLASH_ASSIGN => self.parse_assign_expression(tok, expr),
+            Token::LPAREN => self.parse_call_expression(expr),
+            Token::LBRACKET => self.parse_index_expression(expr),
+            _ => Err(self.cur_error("infix expression", "an infix operator")),
         }
     }
 
-    fn parse_infix_expression(&mut self, tok: Token<'a>, expr: Node<'a>) -> Node<'a> {
-        let precedence = self.cur_precedence();
+    fn parse_infix_expression(&mut self, tok: Token<'a>, expr: Node<'a>) -> ParseResult<'a> {
+        let operator = self.operator_from_tok(tok)?;
+        // `**` is right-associative, so its right operand may itself be a
+        // `**` expression
+        let precedence = match tok {
+            Token::POWER => Precedence::Prefix,
+            _ => self.cur_precedence(),
+        };
         self.next_token();
 
-        let right = self.parse_expression(precedence);
-        Node::InfixExpression {
+        let right = self.parse_expression(precedence)?;
+        Ok(Node::InfixExpression {
             token: tok,
-            operator: Parser::operator_from_tok(tok),
+            span: expr.span().to(self.cur_span()),
+            operator,
             left: Box::new(expr),
-            right: right.map(Box::new),
+            right: Some(Box::new(right)),
+        })
+    }
+
+    fn parse_assign_expression(&mut self, tok: Token<'a>, target: Node<'a>) -> ParseResult<'a> {
+        if !Parser::is_assignable(&target) {
+            return Err(ParseError::new("assignment",
+                                       "an identifier or index expression",
+                                       Some(target.get_token_literal()),
+                                       target.span()));
+        }
+        self.next_token();
+
+        // Assignment is right-associative, so `a = b = 1` assigns `b` first
+        let value = self.parse_expression(Precedence::Lowest)?;
+        Ok(Node::AssignExpression {
+            token: tok,
+            span: target.span().to(self.cur_span()),
+            target: Box::new(target),
+            value: Box::new(value),
+        })
+    }
+
+    // An assignment target is a name, or an index into an assignable target
+    fn is_assignable(mut node: &Node<'a>) -> bool {
+        while let Node::IndexExpression { ref left, .. } = *node {
+            node = left;
         }
+        matches!(*node, Node::Identifier { .. })
     }
 
-    pub fn parse_call_expression(&mut self, expr: Node<'a>) -> Node<'a> {
-        Node::CallExpression {
-            token: self.cur_token.unwrap(),
+    pub fn parse_call_expression(&mut self, expr: Node<'a>) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("call expression without a token");
+        let parameters =
+            self.parse_expression_list(Token::RPAREN, "call arguments", "COMMA or RPAREN")?;
+        Ok(Node::CallExpression {
+            token,
+            span: expr.span().to(self.cur_span()),
             fn_name: Box::new(expr),
-            parameters: self.parse_call_arguments(),
-        }
+            parameters,
+        })
+    }
+
+    pub fn parse_array_literal(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("array literal without a token");
+        let start = self.cur_span();
+        let elements =
+            self.parse_expression_list(Token::RBRACKET, "array literal", "COMMA or RBRACKET")?;
+        Ok(Node::ArrayLiteral {
+            token,
+            span: start.to(self.cur_span()),
+            elements,
+        })
     }
 
-    fn parse_call_arguments(&mut self) -> Vec<Node<'a>> {
-        let mut arguments = Vec::new();
+    pub fn parse_hash_literal(&mut self) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("hash literal without a token");
+        let start = self.cur_span();
+        let mut pairs = Vec::new();
 
-        if let Some(Token::RPAREN) = self.peek_token() {
+        while self.peek_token() != Some(Token::RBRACE) {
             self.next_token();
-            return arguments;
-        }
+            let key = self.parse_expression(Precedence::Lowest)?;
+            self.expect_peek(Token::COLON, "hash literal", "COLON")?;
+            self.next_token();
+            let value = self.parse_expression(Precedence::Lowest)?;
+            pairs.push((key, value));
 
+            if self.peek_token() != Some(Token::RBRACE) {
+                self.expect_peek(Token::COMMA, "hash literal", "COMMA or RBRACE")?;
+            }
+        }
         self.next_token();
 
-        let arg = self.parse_expression(Precedence::Lowest);
+        Ok(Node::HashLiteral {
+            token,
+            span: start.to(self.cur_span()),
+            pairs,
+        })
+    }
 
-        arguments.push(arg.unwrap());
+    pub fn parse_index_expression(&mut self, expr: Node<'a>) -> ParseResult<'a> {
+        let token = self.get_cur_token().expect("index expression without a token");
+        self.next_token();
+        let index = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek(Token::RBRACKET, "index expression", "RBRACKET")?;
+        Ok(Node::IndexExpression {
+            token,
+            span: expr.span().to(self.cur_span()),
+            left: Box::new(expr),
+            index: Box::new(index),
+        })
+    }
 
-        loop {
-            if let Some(Token::COMMA) = self.peek_token() {
-                self.next_token();
-                self.next_token();
+    // Parses comma separated expressions after an opening delimiter, up to
+    // and including `end`
+    fn parse_expression_list(&mut self,
+                             end: Token<'a>,
+                             context: &'static str,
+                             expected: &'static str)
+                             -> Result<Vec<Node<'a>>, ParseError<'a>> {
+        let mut list = Vec::new();
 
-                let arg = self.parse_expression(Precedence::Lowest);
-                arguments.push(arg.unwrap());
-            } else {
-                break;
-            }
+        if self.peek_token() == Some(end) {
+            self.next_token();
+            return Ok(list);
         }
 
         self.next_token();
-        arguments
+        list.push(self.parse_expression(Precedence::Lowest)?);
 
+        while let Some(Token::COMMA) = self.peek_token() {
+            self.next_token();
+            self.next_token();
+
+            list.push(self.parse_expression(Precedence::Lowest)?);
+        }
+
+        self.expect_peek(end, context, expected)?;
+        Ok(list)
     }
 
-    fn parse_block_statement(&mut self) -> Node<'a> {
+    fn parse_block_statement(&mut self) -> ParseResult<'a> {
+        let start = self.cur_span();
 
         self.next_token();
 
         let mut statements = Vec::new();
 
-        while let Some(statement) = self.parse_statement() {
-            statements.push(Box::new(statement));
+        loop {
+            match self.get_cur_token() {
+                Some(Token::RBRACE) => break,
+                Some(Token::SEMICOLON) => {}
+                Some(_) => statements.push(Box::new(self.parse_statement()?)),
+                None => return Err(self.cur_error("block statement", "RBRACE")),
+            }
             self.next_token();
         }
 
-        Node::BlockStatement {
+        Ok(Node::BlockStatement {
             token: Token::LBRACE,
-            statements: statements
-        }
+            span: start.to(self.cur_span()),
+            statements
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lexer::LexError;
+
+    fn sp(start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+            line: 1,
+            column: start + 1,
+        }
+    }
 
     #[test]
     fn test_parser() {
@@ -412,20 +1147,25 @@ mod tests {
             statements: vec![
                 Node::LetStatement {
                     token: Token::LET,
-                    name: Box::new(Node::Identifier {
+                    span: sp(0, 22),
+                    mutable: false,
+                    pattern: Pattern::Identifier(Box::new(Node::Identifier {
                         token: Token::IDENT(
                             "negative_five"
                         ),
+                        span: sp(4, 17),
                         value: "negative_five"
-                    }),
+                    })),
                     value: Box::new(Node::PrefixExpression {
                         token: Token::MINUS,
+                        span: sp(20, 22),
                         operator: "MINUS",
                         right: Some(
                             Box::new(Node::IntegerLiteral {
                                 token: Token::INT(
                                     5
                                 ),
+                                span: sp(21, 22),
                                 value: 5
                             })
                         )
@@ -433,15 +1173,18 @@ mod tests {
                 },
                 Node::ReturnStatement {
                     token: Token::RETURN,
+                    span: sp(24, 45),
                     value: Some(
                         Box::new(Node::PrefixExpression {
                             token: Token::BANG,
+                            span: sp(31, 45),
                             operator: "BANG",
                             right: Some(
                                 Box::new(Node::Identifier {
                                     token: Token::IDENT(
                                         "negative_five"
                                     ),
+                                    span: sp(32, 45),
                                     value: "negative_five"
                                 })
                             )
@@ -450,19 +1193,24 @@ mod tests {
                 },
                 Node::LetStatement {
                     token: Token::LET,
-                    name: Box::new(Node::Identifier {
+                    span: sp(47, 60),
+                    mutable: false,
+                    pattern: Pattern::Identifier(Box::new(Node::Identifier {
                         token: Token::IDENT(
                             "y"
                         ),
+                        span: sp(51, 52),
                         value: "y"
-                    }),
+                    })),
                     value: Box::new(Node::InfixExpression {
                         token: Token::PLUS,
+                        span: sp(55, 60),
                         operator: "PLUS",
                         left: Box::new(Node::IntegerLiteral {
                             token: Token::INT(
                                 4
                             ),
+                            span: sp(55, 56),
                             value: 4
                         }),
                         right: Some(
@@ -470,6 +1218,7 @@ mod tests {
                                 token: Token::INT(
                                     4
                                 ),
+                                span: sp(59, 60),
                                 value: 4
                             })
                         )
@@ -478,7 +1227,7 @@ mod tests {
             ]
         };
 
-        assert_eq!(parser.parse_program(), expected, "AST differs");
+        assert_eq!(parser.parse_program(), Ok(expected), "AST differs");
 
     }
 
@@ -492,34 +1241,43 @@ mod tests {
             statements: vec![
                 Node::BlockStatement {
                     token: Token::LBRACE,
+                    span: sp(0, 25),
                     statements: vec![
                         Box::new(Node::LetStatement {
                             token: Token::LET,
-                            name: Box::new(Node::Identifier {
+                            span: sp(2, 11),
+                            mutable: false,
+                            pattern: Pattern::Identifier(Box::new(Node::Identifier {
                                 token: Token::IDENT(
                                     "a"
                                 ),
+                                span: sp(6, 7),
                                 value: "a"
-                            }),
+                            })),
                             value: Box::new(Node::IntegerLiteral {
                                 token: Token::INT(
                                     4
                                 ),
+                                span: sp(10, 11),
                                 value: 4
                             })
                         }),
                         Box::new(Node::LetStatement {
                             token: Token::LET,
-                            name: Box::new(Node::Identifier {
+                            span: sp(13, 22),
+                            mutable: false,
+                            pattern: Pattern::Identifier(Box::new(Node::Identifier {
                                 token: Token::IDENT(
                                     "b"
                                 ),
+                                span: sp(17, 18),
                                 value: "b"
-                            }),
+                            })),
                             value: Box::new(Node::IntegerLiteral {
                                 token: Token::INT(
                                     5
                                 ),
+                                span: sp(21, 22),
                                 value: 5
                             })
                         })
@@ -528,7 +1286,7 @@ mod tests {
             ]
         };
 
-        assert_eq!(parser.parse_program(), expected, "AST differs");
+        assert_eq!(parser.parse_program(), Ok(expected), "AST differs");
     }
 
     #[test]
@@ -539,49 +1297,64 @@ mod tests {
 
         let expected = Program {
             statements: vec![
-                Node::FunctionLiteral {
-                    token: Token::IDENT(
-                        "foo"
-                    ),
-                    parameters: vec![
+                Node::FunctionDeclaration {
+                    token: Token::FUNCTION,
+                    span: sp(0, 39),
+                    name: Box::new(Node::Identifier {
+                        token: Token::IDENT(
+                            "foo"
+                        ),
+                        span: sp(3, 6),
+                        value: "foo"
+                    }),
+                    parameters: Rc::new(vec![
                         Node::Identifier {
                             token: Token::IDENT(
                                 "bar"
                             ),
+                            span: sp(7, 10),
                             value: "bar"
                         },
                         Node::Identifier {
                             token: Token::IDENT(
                                 "baz"
                             ),
+                            span: sp(12, 15),
                             value: "baz"
                         }
-                    ],
-                    body: Box::new(Node::BlockStatement {
+                    ]),
+                    body: Rc::new(Node::BlockStatement {
                         token: Token::LBRACE,
+                        span: sp(17, 39),
                         statements: vec![
                             Box::new(Node::LetStatement {
                                 token: Token::LET,
-                                name: Box::new(Node::Identifier {
+                                span: sp(18, 27),
+                                mutable: false,
+                                pattern: Pattern::Identifier(Box::new(Node::Identifier {
                                     token: Token::IDENT(
                                         "x"
                                     ),
+                                    span: sp(22, 23),
                                     value: "x"
-                                }),
+                                })),
                                 value: Box::new(Node::IntegerLiteral {
                                     token: Token::INT(
                                         5
                                     ),
+                                    span: sp(26, 27),
                                     value: 5
                                 })
                             }),
                             Box::new(Node::ReturnStatement {
                                 token: Token::RETURN,
+                                span: sp(29, 37),
                                 value: Some(
                                     Box::new(Node::Identifier {
                                         token: Token::IDENT(
                                             "x"
                                         ),
+                                        span: sp(36, 37),
                                         value: "x"
                                     })
                                 )
@@ -592,7 +1365,7 @@ mod tests {
             ]
         };
 
-        assert_eq!(parser.parse_program(), expected);
+        assert_eq!(parser.parse_program(), Ok(expected));
     }
 
     #[test]
@@ -605,10 +1378,12 @@ mod tests {
             statements: vec![
                 Node::CallExpression {
                     token: Token::LPAREN,
+                    span: sp(0, 13),
                     fn_name: Box::new(Node::Identifier {
                         token: Token::IDENT(
                             "foo"
                         ),
+                        span: sp(0, 3),
                         value: "foo"
                     }),
                     parameters: vec![
@@ -616,12 +1391,14 @@ mod tests {
                             token: Token::IDENT(
                                 "bar"
                             ),
+                            span: sp(4, 7),
                             value: "bar"
                         },
                         Node::Identifier {
                             token: Token::IDENT(
                                 "baz"
                             ),
+                            span: sp(9, 12),
                             value: "baz"
                         }
                     ]
@@ -630,7 +1407,146 @@ mod tests {
         };
 
 
-        assert_eq!(parser.parse_program(), expected);
+        assert_eq!(parser.parse_program(), Ok(expected));
+    }
+
+    #[test]
+    fn test_array_and_index() {
+        let input = "[1, x][0]";
+        let mut parser = Parser::new(Lexer::new(input));
+
+        let expected = Program {
+            statements: vec![
+                Node::IndexExpression {
+                    token: Token::LBRACKET,
+                    span: sp(0, 9),
+                    left: Box::new(Node::ArrayLiteral {
+                        token: Token::LBRACKET,
+                        span: sp(0, 6),
+                        elements: vec![
+                            Node::IntegerLiteral {
+                                token: Token::INT(1),
+                                span: sp(1, 2),
+                                value: 1
+                            },
+                            Node::Identifier {
+                                token: Token::IDENT("x"),
+                                span: sp(4, 5),
+                                value: "x"
+                            }
+                        ]
+                    }),
+                    index: Box::new(Node::IntegerLiteral {
+                        token: Token::INT(0),
+                        span: sp(7, 8),
+                        value: 0
+                    })
+                }
+            ]
+        };
+
+        assert_eq!(parser.parse_program(), Ok(expected));
+    }
+
+    #[test]
+    fn test_assign_expression() {
+        let input = "xs[0] += 1";
+        let mut parser = Parser::new(Lexer::new(input));
+
+        let expected = Program {
+            statements: vec![
+                Node::AssignExpression {
+                    token: Token::PLUS_ASSIGN,
+                    span: sp(0, 10),
+                    target: Box::new(Node::IndexExpression {
+                        token: Token::LBRACKET,
+                        span: sp(0, 5),
+                        left: Box::new(Node::Identifier {
+                            token: Token::IDENT("xs"),
+                            span: sp(0, 2),
+                            value: "xs"
+                        }),
+                        index: Box::new(Node::IntegerLiteral {
+                            token: Token::INT(0),
+                            span: sp(3, 4),
+                            value: 0
+                        })
+                    }),
+                    value: Box::new(Node::IntegerLiteral {
+                        token: Token::INT(1),
+                        span: sp(9, 10),
+                        value: 1
+                    })
+                }
+            ]
+        };
+
+        assert_eq!(parser.parse_program(), Ok(expected));
+    }
+
+    #[test]
+    fn test_let_patterns() {
+        let input = "let [a, ...rest] = xs; let {b} = h;";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program().unwrap();
+
+        let ident = |name, start, end| {
+            Node::Identifier {
+                token: Token::IDENT(name),
+                span: sp(start, end),
+                value: name,
+            }
+        };
+        let patterns: Vec<&Pattern> = program.statements
+            .iter()
+            .map(|statement| match *statement {
+                Node::LetStatement { ref pattern, .. } => pattern,
+                ref other => panic!("expected let statement, got {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(patterns,
+                   vec![
+                       &Pattern::Array {
+                           token: Token::LBRACKET,
+                           span: sp(4, 16),
+                           elements: vec![Pattern::Identifier(Box::new(ident("a", 5, 6)))],
+                           rest: Some(Box::new(ident("rest", 11, 15))),
+                       },
+                       &Pattern::Hash {
+                           token: Token::LBRACE,
+                           span: sp(27, 30),
+                           keys: vec![ident("b", 28, 29)],
+                       },
+                   ]);
+    }
+
+    #[test]
+    fn test_hash_literal() {
+        let input = r#"{"a": 1}"#;
+        let mut parser = Parser::new(Lexer::new(input));
+        let expected = Program {
+            statements: vec![
+                Node::HashLiteral {
+                    token: Token::LBRACE,
+                    span: sp(0, 8),
+                    pairs: vec![
+                        (Node::StringLiteral {
+                            token: Token::STRING("a"),
+                            span: sp(1, 4),
+                            value: "a".to_owned()
+                        },
+                         Node::IntegerLiteral {
+                            token: Token::INT(1),
+                            span: sp(6, 7),
+                            value: 1
+                        })
+                    ]
+                }
+            ]
+        };
+
+        assert_eq!(parser.parse_program(), Ok(expected));
     }
 
     #[test]
@@ -643,12 +1559,568 @@ mod tests {
             statements: vec![
                 Node::Boolean {
                     token: Token::TRUE,
+                    span: sp(0, 4),
                     value: true,
                 }
             ]
         };
 
 
-        assert_eq!(parser.parse_program(), expected);
+        assert_eq!(parser.parse_program(), Ok(expected));
+    }
+
+    #[test]
+    fn test_if_expression() {
+        let input = "if (x < y) { x } else { y }";
+        let mut parser = Parser::new(Lexer::new(input));
+
+        let expected = Program {
+            statements: vec![
+                Node::IfExpression {
+                    token: Token::IF,
+                    span: sp(0, 27),
+                    condition: Box::new(Node::InfixExpression {
+                        token: Token::LT,
+                        span: sp(4, 9),
+                        operator: "LT",
+                        left: Box::new(Node::Identifier {
+                            token: Token::IDENT("x"),
+                            span: sp(4, 5),
+                            value: "x"
+                        }),
+                        right: Some(Box::new(Node::Identifier {
+                            token: Token::IDENT("y"),
+                            span: sp(8, 9),
+                            value: "y"
+                        }))
+                    }),
+                    consequence: Box::new(Node::BlockStatement {
+                        token: Token::LBRACE,
+                        span: sp(11, 16),
+                        statements: vec![
+                            Box::new(Node::Identifier {
+                                token: Token::IDENT("x"),
+                                span: sp(13, 14),
+                                value: "x"
+                            })
+                        ]
+                    }),
+                    alternative: Some(Box::new(Node::BlockStatement {
+                        token: Token::LBRACE,
+                        span: sp(22, 27),
+                        statements: vec![
+                            Box::new(Node::Identifier {
+                                token: Token::IDENT("y"),
+                                span: sp(24, 25),
+                                value: "y"
+                            })
+                        ]
+                    }))
+                }
+            ]
+        };
+
+        assert_eq!(parser.parse_program(), Ok(expected));
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        let input = "if (a) { 1 } else if (b) { 2 } else { 3 }";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program().unwrap();
+
+        match program.statements[0] {
+            Node::IfExpression { alternative: Some(ref alternative), .. } => {
+                match **alternative {
+                    Node::IfExpression { alternative: Some(ref last), span, .. } => {
+                        assert_eq!(span, sp(18, 41));
+                        assert_eq!(last.span(), sp(36, 41));
+                    }
+                    ref other => panic!("expected else if, got {:?}", other),
+                }
+            }
+            ref other => panic!("expected if expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let cases = vec![
+            ("-a * b", "((-a) * b)"),
+            ("!-a", "(!(-a))"),
+            ("a + b + c", "((a + b) + c)"),
+            ("a + b - c", "((a + b) - c)"),
+            ("a * b * c", "((a * b) * c)"),
+            ("a * b / c", "((a * b) / c)"),
+            ("a + b / c", "(a + (b / c))"),
+            ("a * b + c", "((a * b) + c)"),
+            ("a + b * c + d / e - f", "(((a + (b * c)) + (d / e)) - f)"),
+            ("3 + 4; -5 * 5", "(3 + 4)\n((-5) * 5)"),
+            ("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
+            ("-9223372036854775807", "(-9223372036854775807)"),
+            ("5 < 4 != 3 > 4", "((5 < 4) != (3 > 4))"),
+            ("a <= b == c >= d", "((a <= b) == (c >= d))"),
+            ("a || b && c", "(a || (b && c))"),
+            ("a && b || c && d", "((a && b) || (c && d))"),
+            ("a || b || c", "((a || b) || c)"),
+            ("a == b && c != d", "((a == b) && (c != d))"),
+            ("!a && b < c + 1", "((!a) && (b < (c + 1)))"),
+            ("a * b % c", "((a * b) % c)"),
+            ("a + b % c", "(a + (b % c))"),
+            ("-2 ** 2", "(-(2 ** 2))"),
+            ("2 ** 3 ** 2", "(2 ** (3 ** 2))"),
+            ("a = b = c", "(a = (b = c))"),
+            ("a += b * c || d", "(a += ((b * c) || d))"),
+            ("h[k][0] -= -1", "(((h[k])[0]) -= (-1))"),
+            ("let x = y *= 2;", "let x = (y *= 2);"),
+            ("let mut x = 1; x = 2", "let mut x = 1;\n(x = 2)"),
+            ("let [a, [b], ...rest] = xs;", "let [a, [b], ...rest] = xs;"),
+            ("let [...rest] = xs;", "let [...rest] = xs;"),
+            ("let mut {name, age} = person;", "let mut {name, age} = person;"),
+            ("let [] = []; let {} = {};", "let [] = [];\nlet {} = {};"),
+            ("2 ** -1", "(2 ** (-1))"),
+            ("a ** b * c", "((a ** b) * c)"),
+            ("~a ** b", "(~(a ** b))"),
+            ("f(x) ** 2", "(f(x) ** 2)"),
+            ("a + b << c", "((a + b) << c)"),
+            ("a << b & c", "((a << b) & c)"),
+            ("a | b ^ c & d", "(a | (b ^ (c & d)))"),
+            ("a & b | c ^ d", "((a & b) | (c ^ d))"),
+            ("a & b == c", "((a & b) == c)"),
+            ("a < b | c", "(a < (b | c))"),
+            ("a | b && c", "((a | b) && c)"),
+            ("a * [1, 2, 3, 4][b * c] * d", "((a * ([1, 2, 3, 4][(b * c)])) * d)"),
+            ("add(a * b[2], b[1], 2 * [1, 2][1])",
+             "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))"),
+            ("-a[0]", "(-(a[0]))"),
+            ("a ** b[0]", "(a ** (b[0]))"),
+            ("f(x)[0]", "(f(x)[0])"),
+            ("a[0](1)", "(a[0])(1)"),
+            ("a[0][1]", "((a[0])[1])"),
+            ("[]", "[]"),
+            (r#"let h = {"a": 1 + 2, b: c, 3: [4]};"#, r#"let h = {"a": (1 + 2), b: c, 3: [4]};"#),
+            ("let h = {};", "let h = {};"),
+            ("{ a }", "{ a }"),
+            ("{}", "{}"),
+            ("{ f({x: 1}); }", "{ f({x: 1}) }"),
+            ("{ if (a) { b } }", "{ if (a) { b } }"),
+            ("{ [a][0]: 1 }", "{([a][0]): 1}"),
+            ("{ f(x) : 1 }[y]", "({f(x): 1}[y])"),
+            ("fn add(a, b) { a + b }", "fn add(a, b) { (a + b) }"),
+            ("fn(a) { a }(1)", "fn(a) { a }(1)"),
+            ("let f = fn(a) { fn g() { a } g };", "let f = fn(a) { fn g() { a } g };"),
+            ("f({})[x]", "(f({})[x])"),
+            ("3 + 4 * 5 == 3 * 1 + 4 * 5", "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))"),
+            ("true", "true"),
+            ("3 > 5 == false", "((3 > 5) == false)"),
+            ("1 + (2 + 3) + 4", "((1 + (2 + 3)) + 4)"),
+            ("(5 + 5) * 2", "((5 + 5) * 2)"),
+            ("2 / (5 + 5)", "(2 / (5 + 5))"),
+            ("-(5 + 5)", "(-(5 + 5))"),
+            ("!(true == true)", "(!(true == true))"),
+            ("((a))", "a"),
+            ("a + add(b * c) + d", "((a + add((b * c))) + d)"),
+            ("add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))",
+             "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))"),
+            ("add(a + b + c * d / f + g)", "add((((a + b) + ((c * d) / f)) + g))"),
+            ("-add(1)", "(-add(1))"),
+            ("(fn(x) { x })(5)", "fn(x) { x }(5)"),
+            ("fn(x) { x }(5)", "fn(x) { x }(5)"),
+            ("(add)(1) * 2", "(add(1) * 2)"),
+            ("if ((a)) { b } else { c }", "if (a) { b } else { c }"),
+        ];
+
+        for (input, expected) in cases {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_string_literal() {
+        let input = r#"let s = "a\tb";"#;
+        let mut parser = Parser::new(Lexer::new(input));
+
+        let expected = Program {
+            statements: vec![
+                Node::LetStatement {
+                    token: Token::LET,
+                    span: sp(0, 14),
+                    mutable: false,
+                    pattern: Pattern::Identifier(Box::new(Node::Identifier {
+                        token: Token::IDENT("s"),
+                        span: sp(4, 5),
+                        value: "s"
+                    })),
+                    value: Box::new(Node::StringLiteral {
+                        token: Token::STRING("a\\tb"),
+                        span: sp(8, 14),
+                        value: "a\tb".to_owned()
+                    })
+                }
+            ]
+        };
+
+        assert_eq!(parser.parse_program(), Ok(expected));
+    }
+
+    #[test]
+    fn test_float_literal() {
+        let input = "let r = 2.5e-3;";
+        let mut parser = Parser::new(Lexer::new(input));
+
+        let expected = Program {
+            statements: vec![
+                Node::LetStatement {
+                    token: Token::LET,
+                    span: sp(0, 14),
+                    mutable: false,
+                    pattern: Pattern::Identifier(Box::new(Node::Identifier {
+                        token: Token::IDENT("r"),
+                        span: sp(4, 5),
+                        value: "r"
+                    })),
+                    value: Box::new(Node::FloatLiteral {
+                        token: Token::FLOAT(2.5e-3),
+                        span: sp(8, 14),
+                        value: 2.5e-3
+                    })
+                }
+            ]
+        };
+
+        assert_eq!(parser.parse_program(), Ok(expected));
+    }
+
+    #[test]
+    fn test_loops() {
+        let cases = vec![
+            ("while (x < 10) { x }", "while ((x < 10)) { x }"),
+            ("loop { break; }", "loop { break; }"),
+            ("loop { if (a) { continue } else { break } }",
+             "loop { if (a) { continue; } else { break; } }"),
+            ("for (let i = 0; i < 10; f(i)) { i }", "for (let i = 0; (i < 10); f(i)) { i }"),
+            ("for (;;) { break }", "for (;;) { break; }"),
+            ("for (i; ; ) { }", "for (i;;) {}"),
+            ("while (true) { let f = fn() { 1 }; break; }",
+             "while (true) { let f = fn() { 1 }; break; }"),
+        ];
+
+        for (input, expected) in cases {
+            let mut parser = Parser::new(Lexer::new(input));
+            let program = parser.parse_program().unwrap();
+            assert_eq!(program.to_string(), expected, "{}", input);
+        }
+
+        let mut parser = Parser::new(Lexer::new("loop { x }"));
+        let expected = Program {
+            statements: vec![
+                Node::LoopStatement {
+                    token: Token::LOOP,
+                    span: sp(0, 10),
+                    body: Box::new(Node::BlockStatement {
+                        token: Token::LBRACE,
+                        span: sp(5, 10),
+                        statements: vec![
+                            Box::new(Node::Identifier {
+                                token: Token::IDENT("x"),
+                                span: sp(7, 8),
+                                value: "x"
+                            })
+                        ]
+                    })
+                }
+            ]
+        };
+        assert_eq!(parser.parse_program(), Ok(expected));
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nest = |open: &str, inner: &str, close: &str, n: usize| {
+            format!("{}{}{}", open.repeat(n), inner, close.repeat(n))
+        };
+
+        let input = nest("(", "1", ")", 254);
+        assert!(Parser::new(Lexer::new(&input)).parse_program().is_ok());
+
+        let input = nest("(", "1", ")", 200_000);
+        let errors = Parser::new(Lexer::new(&input)).parse_program().unwrap_err();
+        assert_eq!(errors,
+                   vec![ParseError {
+                            kind: ParseErrorKind::NestedTooDeeply,
+                            span: sp(255, 256),
+                        }]);
+        assert_eq!(errors[0].to_string(), "expression nested too deeply");
+
+        // Chains of left-associative operators nest their left operands as
+        // deeply as they are long, but never recurse while parsing
+        let inputs = vec![
+            nest("", "1", " + 1", 300),
+            nest("", "\"a\"", " + \"a\"", 260),
+            nest("", "1", " + 1", 100_000),
+            nest("", "xs", "[0]", 100_000),
+            nest("", "f", "()", 100_000),
+            nest("", "xs", "[0]", 100_000) + " = 1",
+        ];
+        for input in inputs {
+            assert!(Parser::new(Lexer::new(&input)).parse_program().is_ok(), "{:.20}", input);
+        }
+
+        let inputs = vec![
+            nest("-", "1", "", 1_000),
+            nest("{", "", "}", 1_000),
+            nest("[", "", "]", 1_000),
+            format!("let {} = xs;", nest("[", "a", "]", 1_000)),
+            nest("fn() { ", "1", " }", 1_000),
+        ];
+        for input in inputs {
+            let errors = Parser::new(Lexer::new(&input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].kind, ParseErrorKind::NestedTooDeeply, "{:.20}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = vec![
+            ("let = 5;",
+             ParseError::new("let statement",
+                             "IDENT, LBRACKET or LBRACE",
+                             Some(Token::ASSIGN),
+                             sp(4, 5))),
+            ("let x 5;", ParseError::new("let statement", "ASSIGN", Some(Token::INT(5)), sp(6, 7))),
+            ("let x = ;",
+             ParseError::new("expression", "an expression", Some(Token::SEMICOLON), sp(8, 9))),
+            ("fn(x, 1) { x }",
+             ParseError::new("function parameters", "IDENT", Some(Token::INT(1)), sp(6, 7))),
+            ("fn(x y) { x }",
+             ParseError::new("function parameters",
+                             "COMMA or RPAREN",
+                             Some(Token::IDENT("y")),
+                             sp(5, 6))),
+            ("fn(x) x",
+             ParseError::new("function literal", "LBRACE", Some(Token::IDENT("x")), sp(6, 7))),
+            ("add(1, 2", ParseError::new("call arguments", "COMMA or RPAREN", None, sp(7, 8))),
+            ("{ let a = 1;", ParseError::new("block statement", "RBRACE", None, sp(11, 12))),
+            ("5 +", ParseError::new("expression", "an expression", None, sp(2, 3))),
+            ("if x { 1 }",
+             ParseError::new("if expression", "LPAREN", Some(Token::IDENT("x")), sp(3, 4))),
+            ("(1 + 2", ParseError::new("grouped expression", "RPAREN", None, sp(5, 6))),
+            ("let if = 1;",
+             ParseError::new("let statement", RESERVED_NAME, Some(Token::IF), sp(4, 6))),
+            ("let mut = 1;",
+             ParseError::new("let statement",
+                             "IDENT, LBRACKET or LBRACE",
+                             Some(Token::ASSIGN),
+                             sp(8, 9))),
+            ("let [a, ...rest, b] = xs;",
+             ParseError::new("array pattern", "RBRACKET", Some(Token::COMMA), sp(15, 16))),
+            ("let [a b] = xs;",
+             ParseError::new("array pattern",
+                             "COMMA or RBRACKET",
+                             Some(Token::IDENT("b")),
+                             sp(7, 8))),
+            ("let [1] = xs;",
+             ParseError::new("array pattern",
+                             "IDENT, LBRACKET or LBRACE",
+                             Some(Token::INT(1)),
+                             sp(5, 6))),
+            ("let [...[a]] = xs;",
+             ParseError::new("array pattern", "IDENT", Some(Token::LBRACKET), sp(8, 9))),
+            ("let {name, fn} = person;",
+             ParseError::new("hash pattern", RESERVED_NAME, Some(Token::FUNCTION), sp(11, 13))),
+            ("let {name: n} = person;",
+             ParseError::new("hash pattern",
+                             "COMMA or RBRACE",
+                             Some(Token::COLON),
+                             sp(9, 10))),
+            ("let mut mut = 1;",
+             ParseError::new("let statement", RESERVED_NAME, Some(Token::MUT), sp(8, 11))),
+            ("fn(x, true) { x }",
+             ParseError::new("function parameters", RESERVED_NAME, Some(Token::TRUE), sp(6, 10))),
+            ("break;",
+             ParseError::new("loop control statement",
+                             "an enclosing loop",
+                             Some(Token::BREAK),
+                             sp(0, 5))),
+            ("loop { fn() { continue } }",
+             ParseError::new("loop control statement",
+                             "an enclosing loop",
+                             Some(Token::CONTINUE),
+                             sp(14, 22))),
+            ("for (let i = 0 i) { }",
+             ParseError::new("for statement", "SEMICOLON", Some(Token::IDENT("i")), sp(15, 16))),
+            ("for (; true ) { }",
+             ParseError::new("for statement", "SEMICOLON", Some(Token::RPAREN), sp(12, 13))),
+            ("while true { }",
+             ParseError::new("while statement", "LPAREN", Some(Token::TRUE), sp(6, 10))),
+            ("let s = \"abc",
+             ParseError::new("expression",
+                             "an expression",
+                             Some(Token::ILLEGAL(LexError::UnterminatedString)),
+                             sp(8, 12))),
+            ("if (x) { 1 } else 2",
+             ParseError::new("else branch", "IF or LBRACE", Some(Token::INT(2)), sp(18, 19))),
+            ("[1, 2 3]",
+             ParseError::new("array literal", "COMMA or RBRACKET", Some(Token::INT(3)), sp(6, 7))),
+            (r#"let h = {"a" 1};"#,
+             ParseError::new("hash literal", "COLON", Some(Token::INT(1)), sp(13, 14))),
+            (r#"let h = {"a": 1 "b": 2};"#,
+             ParseError::new("hash literal",
+                             "COMMA or RBRACE",
+                             Some(Token::STRING("b")),
+                             sp(16, 19))),
+            ("let f = fn g() { 1 };",
+             ParseError::new("function literal", "LPAREN", Some(Token::IDENT("g")), sp(11, 12))),
+            ("fn let() { 1 }",
+             ParseError::new("function declaration", RESERVED_NAME, Some(Token::LET), sp(3, 6))),
+            ("fn 1() { 1 }",
+             ParseError::new("function declaration",
+                             "IDENT or LPAREN",
+                             Some(Token::INT(1)),
+                             sp(3, 4))),
+            ("xs[1;",
+             ParseError::new("index expression", "RBRACKET", Some(Token::SEMICOLON), sp(4, 5))),
+            ("1 = x;",
+             ParseError::new("assignment",
+                             "an identifier or index expression",
+                             Some(Token::INT(1)),
+                             sp(0, 1))),
+            ("a + b /= 2;",
+             ParseError::new("assignment",
+                             "an identifier or index expression",
+                             Some(Token::PLUS),
+                             sp(0, 5))),
+            ("f()[0] = 1;",
+             ParseError::new("assignment",
+                             "an identifier or index expression",
+                             Some(Token::LBRACKET),
+                             sp(0, 6))),
+        ];
+
+        for (input, expected) in cases {
+            let mut parser = Parser::new(Lexer::new(input));
+            assert_eq!(parser.parse_program(), Err(vec![expected]), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let duplicate = |context, name, span| {
+            ParseError {
+                kind: ParseErrorKind::DuplicateName { context, name },
+                span,
+            }
+        };
+        let cases = vec![
+            ("let [a, a] = [1, 2];", duplicate("let statement", "a", sp(8, 9))),
+            ("let [a, [b, a]] = xs;", duplicate("let statement", "a", sp(12, 13))),
+            ("let [a, ...a] = xs;", duplicate("let statement", "a", sp(11, 12))),
+            ("let {a, b, a} = h;", duplicate("let statement", "a", sp(11, 12))),
+            ("let [{a}, a] = xs;", duplicate("let statement", "a", sp(10, 11))),
+            ("fn f(a, a) { a }", duplicate("function parameters", "a", sp(8, 9))),
+            ("let f = fn(x, y, x) { x };", duplicate("function parameters", "x", sp(17, 18))),
+        ];
+
+        for (input, expected) in cases {
+            let mut parser = Parser::new(Lexer::new(input));
+            assert_eq!(parser.parse_program(), Err(vec![expected]), "{}", input);
+        }
+
+        assert_eq!(duplicate("let statement", "a", sp(8, 9)).to_string(),
+                   "a is bound more than once in let statement");
+        for input in &["let [a, b, ...c] = xs;", "let a = 1; let a = 2;", "fn f(a) { fn(a) { a } }"] {
+            assert!(Parser::new(Lexer::new(input)).parse_program().is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn test_integer_literal_range() {
+        let mut parser = Parser::new(Lexer::new("9223372036854775808"));
+        assert_eq!(parser.parse_program(),
+                   Err(vec![ParseError::new("integer literal",
+                                            INTEGER_IN_RANGE,
+                                            Some(Token::INT(1 << 63)),
+                                            sp(0, 19))]));
+
+        let program = Parser::new(Lexer::new("-9223372036854775808 * 1")).parse_program().unwrap();
+        assert_eq!(program.to_string(), "(-9223372036854775808 * 1)");
+
+        // `**` binds tighter than `-`, so the literal is not negated here
+        let mut parser = Parser::new(Lexer::new("-9223372036854775808 ** 0"));
+        assert_eq!(parser.parse_program(),
+                   Err(vec![ParseError::new("integer literal",
+                                            INTEGER_IN_RANGE,
+                                            Some(Token::INT(1 << 63)),
+                                            sp(1, 20))]));
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn test_big_integer_literal() {
+        let program = Parser::new(Lexer::new("9223372036854775808 + 0x1_0000_0000_0000_0000"))
+            .parse_program()
+            .unwrap();
+        assert_eq!(program.to_string(), "(9223372036854775808 + 18446744073709551616)");
+    }
+
+    #[test]
+    fn test_collects_multiple_errors() {
+        let input = "let = 1; let y = 2; let z 3; y;";
+        let mut parser = Parser::new(Lexer::new(input));
+
+        let errors = parser.parse_program().unwrap_err();
+        assert_eq!(errors,
+                   vec![
+                       ParseError::new("let statement",
+                                       "IDENT, LBRACKET or LBRACE",
+                                       Some(Token::ASSIGN),
+                                       sp(4, 5)),
+                       ParseError::new("let statement", "ASSIGN", Some(Token::INT(3)), sp(26, 27)),
+                   ]);
+        assert_eq!(errors[0].to_string(),
+                   "expected IDENT, LBRACKET or LBRACE in let statement, found ASSIGN");
+
+        // Recovery skips the blocks that an error is inside of, and the rest
+        // of the statement around them
+        let inputs = vec![
+            ("let f = fn() { break; }; 1", vec![sp(15, 20)]),
+            ("fn f() { { continue; } 1 } let x = ;", vec![sp(11, 19), sp(35, 36)]),
+            ("if (true) { break; } else { 1 }; let x = ;", vec![sp(12, 17), sp(41, 42)]),
+            ("if (true) { break; } else { 1 } let x = ;", vec![sp(12, 17), sp(40, 41)]),
+            ("fn() { break; }(1); 2", vec![sp(7, 12)]),
+            ("}; let x = ;", vec![sp(0, 1), sp(11, 12)]),
+        ];
+        for (input, spans) in inputs {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors.iter().map(|e| e.span).collect::<Vec<_>>(), spans, "{}", input);
+        }
+
+        let mut parser = Parser::new(Lexer::new("\"abc"));
+        assert_eq!(parser.parse_program().unwrap_err()[0].to_string(),
+                   "expected an expression in expression, found unterminated string literal");
+    }
+
+    #[test]
+    fn test_node_spans() {
+        let input = "let add = fn(x, y) {\n  x + y\n};\nadd(1, 2)";
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program().unwrap();
+
+        let spans: Vec<Span> = program.statements.iter().map(Node::span).collect();
+        assert_eq!(spans,
+                   vec![
+                       Span { start: 0, end: 30, line: 1, column: 1 },
+                       Span { start: 32, end: 41, line: 4, column: 1 },
+                   ]);
+
+        match program.statements[0] {
+            Node::LetStatement { ref value, .. } => {
+                assert_eq!(value.span(), Span { start: 10, end: 30, line: 1, column: 11 });
+            }
+            ref other => panic!("expected let statement, got {:?}", other),
+        }
     }
 }
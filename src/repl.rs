@@ -0,0 +1,140 @@
+use std::io::{self, BufRead, Write};
+
+use typed_arena::Arena;
+
+use check::Checker;
+use eval::{Environment, Object};
+use lexer::Lexer;
+use parser::Parser;
+use lexer::LexError;
+use token::Token;
+
+const PROMPT: &str = ">> ";
+const CONTINUATION_PROMPT: &str = ".. ";
+
+/// Reads lines from `input` until EOF, evaluating each complete chunk of
+/// source and writing its result to `output`. Bindings persist between
+/// chunks, and a chunk keeps growing while it has unclosed `{` or `/*`.
+pub fn start<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    // Function bodies stored in the environment borrow from the source they
+    // were parsed from, so every chunk is kept until the session ends
+    let sources = Arena::new();
+    let env = Environment::new();
+    let mut checker = Checker::new();
+    let mut buffer = String::new();
+
+    loop {
+        output.write_all(if buffer.is_empty() { PROMPT } else { CONTINUATION_PROMPT }.as_bytes())?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        buffer.push_str(&line);
+
+        if is_incomplete(&buffer) {
+            continue;
+        }
+
+        if buffer.trim().is_empty() {
+            buffer.clear();
+            continue;
+        }
+        let source: &str = sources.alloc(buffer.split_off(0));
+
+        let program = match Parser::new(Lexer::new(source)).parse_program() {
+            Ok(program) => program,
+            Err(errors) => {
+                for e in errors {
+                    writeln!(output, "parse error at {}: {}", e.span, e)?;
+                }
+                continue;
+            }
+        };
+
+        if let Err(errors) = checker.check_program(&program) {
+            for e in errors {
+                writeln!(output, "check error at {}: {}", e.span, e)?;
+            }
+            continue;
+        }
+
+        match env.eval_program(&program) {
+            Ok(Object::Unit) => {}
+            Ok(value) => writeln!(output, "{}", value)?,
+            Err(e) => {
+                // Some of the chunk's bindings may not have been made
+                checker.rollback();
+                writeln!(output, "error at {}: {}", e.span, e)?
+            }
+        }
+    }
+}
+
+fn is_incomplete(source: &str) -> bool {
+    let mut depth = 0;
+    for tok in Lexer::new(source) {
+        match tok.token {
+            Token::LBRACE => depth += 1,
+            Token::RBRACE => depth -= 1,
+            Token::ILLEGAL(LexError::UnterminatedComment) => return true,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &str) -> String {
+        let mut output = Vec::new();
+        start(input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_bindings_persist_across_lines() {
+        let output = run("let a = 5;\nlet b = a * 2;\nb + 1\n");
+        assert_eq!(output, ">> >> >> 11\n>> \n");
+    }
+
+    #[test]
+    fn test_multi_line_function() {
+        let output = run("let add = fn(x, y) {\n  x + y\n};\nadd(1, 2)\n");
+        assert_eq!(output, ">> .. .. >> 3\n>> \n");
+    }
+
+    #[test]
+    fn test_multi_line_comment() {
+        let output = run("/* a\ncomment */ 1 + 1 // trailing\n");
+        assert_eq!(output, ">> .. 2\n>> \n");
+    }
+
+    #[test]
+    fn test_errors_do_not_end_session() {
+        let output = run("foo\n1 + 1\n");
+        assert_eq!(output, ">> error at 1:1: identifier not found: foo\n>> 2\n>> \n");
+
+        let output = run("let = 5;\n1 + 1\n");
+        assert_eq!(output,
+                   ">> parse error at 1:5: expected IDENT, LBRACKET or LBRACE in let statement, \
+                    found ASSIGN\n>> 2\n>> \n");
+    }
+
+    #[test]
+    fn test_assignment_checked_across_lines() {
+        let output = run("let mut a = 1;\nlet b = 2;\na += b\nb = a\nb\n");
+        assert_eq!(output,
+                   ">> >> >> 3\n>> check error at 1:1: cannot assign to immutable binding: b\n>> 2\n>> \n");
+
+        // `a` is immutable again after a chunk fails between its bindings
+        let output = run("let mut a = 1;\nlet a = 2; let mut a = 1 / 0;\na = 3\na\n");
+        assert_eq!(output,
+                   ">> >> error at 1:24: division by zero\n>> check error at 1:1: cannot assign to \
+                    immutable binding: a\n>> 2\n>> \n");
+    }
+}
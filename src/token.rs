@@ -1,44 +1,111 @@
 #![allow(non_camel_case_types)]
 
+use std::collections::HashMap;
+use std::fmt;
+
+use lexer::LexError;
 use parser::Precedence;
 
+/// A region of source text. `start` and `end` are offsets into the input,
+/// `line` and `column` (both 1-based) locate `start`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Returns a span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpannedToken<'a> {
+    pub token: Token<'a>,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Token<'a> {
-    ILLEGAL,
+    ILLEGAL(LexError),
     EOF,
 
+    // Trivia, only produced by `Lexer::with_comments`
+    COMMENT(&'a str),
+
     // Identifiers + literals
     IDENT(&'a str),
     INT(u64),
+    // The source text of an integer literal too large for INT, only produced
+    // when the `bigint` feature is enabled
+    #[cfg(feature = "bigint")]
+    BIG_INT(&'a str),
+    FLOAT(f64),
     STRING(&'a str),
 
     // Operators
     ASSIGN,
+    PLUS_ASSIGN,
+    MINUS_ASSIGN,
+    ASTERISK_ASSIGN,
+    SLASH_ASSIGN,
     PLUS,
     MINUS,
     GT,
     LT,
+    GT_EQ,
+    LT_EQ,
+    AND,
+    OR,
     BANG,
     ASTERISK,
     SLASH,
+    PERCENT,
+    POWER,
+    AMPERSAND,
+    PIPE,
+    CARET,
+    TILDE,
+    SHL,
+    SHR,
     EQ,
     NOT_EQ,
 
     // Delimiters
     COMMA,
     SEMICOLON,
+    COLON,
+    ELLIPSIS,
 
     LPAREN,
     RPAREN,
     LBRACE,
     RBRACE,
+    LBRACKET,
+    RBRACKET,
 
     // Keywords
     FUNCTION,
     LET,
+    MUT,
     WHILE,
     FOR,
     LOOP,
+    BREAK,
+    CONTINUE,
     TRUE,
     FALSE,
     RETURN,
@@ -46,18 +113,134 @@ pub enum Token<'a> {
     ELSE,
 }
 
+// Prints the token as it would appear in source
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::ILLEGAL(_) => write!(f, "ILLEGAL"),
+            Token::EOF => write!(f, "EOF"),
+            Token::COMMENT(text) => write!(f, "{}", text),
+            Token::IDENT(name) => write!(f, "{}", name),
+            Token::INT(i) => write!(f, "{}", i),
+            #[cfg(feature = "bigint")]
+            Token::BIG_INT(raw) => write!(f, "{}", raw),
+            Token::FLOAT(x) => write!(f, "{:?}", x),
+            Token::STRING(s) => write!(f, "\"{}\"", s),
+            Token::ASSIGN => write!(f, "="),
+            Token::PLUS_ASSIGN => write!(f, "+="),
+            Token::MINUS_ASSIGN => write!(f, "-="),
+            Token::ASTERISK_ASSIGN => write!(f, "*="),
+            Token::SLASH_ASSIGN => write!(f, "/="),
+            Token::PLUS => write!(f, "+"),
+            Token::MINUS => write!(f, "-"),
+            Token::GT => write!(f, ">"),
+            Token::LT => write!(f, "<"),
+            Token::GT_EQ => write!(f, ">="),
+            Token::LT_EQ => write!(f, "<="),
+            Token::AND => write!(f, "&&"),
+            Token::OR => write!(f, "||"),
+            Token::BANG => write!(f, "!"),
+            Token::ASTERISK => write!(f, "*"),
+            Token::SLASH => write!(f, "/"),
+            Token::PERCENT => write!(f, "%"),
+            Token::POWER => write!(f, "**"),
+            Token::AMPERSAND => write!(f, "&"),
+            Token::PIPE => write!(f, "|"),
+            Token::CARET => write!(f, "^"),
+            Token::TILDE => write!(f, "~"),
+            Token::SHL => write!(f, "<<"),
+            Token::SHR => write!(f, ">>"),
+            Token::EQ => write!(f, "=="),
+            Token::NOT_EQ => write!(f, "!="),
+            Token::COMMA => write!(f, ","),
+            Token::SEMICOLON => write!(f, ";"),
+            Token::COLON => write!(f, ":"),
+            Token::ELLIPSIS => write!(f, "..."),
+            Token::LPAREN => write!(f, "("),
+            Token::RPAREN => write!(f, ")"),
+            Token::LBRACE => write!(f, "{{"),
+            Token::RBRACE => write!(f, "}}"),
+            Token::LBRACKET => write!(f, "["),
+            Token::RBRACKET => write!(f, "]"),
+            Token::FUNCTION => write!(f, "fn"),
+            Token::LET => write!(f, "let"),
+            Token::MUT => write!(f, "mut"),
+            Token::WHILE => write!(f, "while"),
+            Token::FOR => write!(f, "for"),
+            Token::LOOP => write!(f, "loop"),
+            Token::BREAK => write!(f, "break"),
+            Token::CONTINUE => write!(f, "continue"),
+            Token::TRUE => write!(f, "true"),
+            Token::FALSE => write!(f, "false"),
+            Token::RETURN => write!(f, "return"),
+            Token::IF => write!(f, "if"),
+            Token::ELSE => write!(f, "else"),
+        }
+    }
+}
+
+lazy_static! {
+    // Every reserved word and the token it lexes to
+    static ref KEYWORDS: HashMap<&'static str, Token<'static>> = {
+        let mut keywords = HashMap::new();
+        keywords.insert("fn", Token::FUNCTION);
+        keywords.insert("let", Token::LET);
+        keywords.insert("mut", Token::MUT);
+        keywords.insert("while", Token::WHILE);
+        keywords.insert("for", Token::FOR);
+        keywords.insert("loop", Token::LOOP);
+        keywords.insert("break", Token::BREAK);
+        keywords.insert("continue", Token::CONTINUE);
+        keywords.insert("true", Token::TRUE);
+        keywords.insert("false", Token::FALSE);
+        keywords.insert("return", Token::RETURN);
+        keywords.insert("if", Token::IF);
+        keywords.insert("else", Token::ELSE);
+        keywords
+    };
+}
+
 impl<'a> Token<'a> {
+    /// Returns the keyword token for `ident`, or `IDENT(ident)` if it is not reserved.
+    pub fn lookup_ident(ident: &'a str) -> Token<'a> {
+        match KEYWORDS.get(ident) {
+            Some(&keyword) => keyword,
+            None => Token::IDENT(ident),
+        }
+    }
+
+    pub fn is_keyword(&self) -> bool {
+        KEYWORDS.values().any(|keyword| keyword == self)
+    }
+
     pub fn get_precedence(&self) -> Precedence {
         match *self {
+            Token::ASSIGN => Precedence::Assign,
+            Token::PLUS_ASSIGN => Precedence::Assign,
+            Token::MINUS_ASSIGN => Precedence::Assign,
+            Token::ASTERISK_ASSIGN => Precedence::Assign,
+            Token::SLASH_ASSIGN => Precedence::Assign,
+            Token::OR => Precedence::LogicalOr,
+            Token::AND => Precedence::LogicalAnd,
             Token::EQ => Precedence::Equals,
             Token::NOT_EQ => Precedence::Equals,
             Token::GT => Precedence::LessGreater,
             Token::LT => Precedence::LessGreater,
+            Token::GT_EQ => Precedence::LessGreater,
+            Token::LT_EQ => Precedence::LessGreater,
+            Token::PIPE => Precedence::BitOr,
+            Token::CARET => Precedence::BitXor,
+            Token::AMPERSAND => Precedence::BitAnd,
+            Token::SHL => Precedence::Shift,
+            Token::SHR => Precedence::Shift,
             Token::PLUS => Precedence::Sum,
             Token::MINUS => Precedence::Sum,
             Token::SLASH => Precedence::Product,
             Token::ASTERISK => Precedence::Product,
+            Token::PERCENT => Precedence::Product,
+            Token::POWER => Precedence::Power,
             Token::LPAREN => Precedence::Call,
+            Token::LBRACKET => Precedence::Index,
             _ => Precedence::Lowest,
         }
     }